@@ -1,216 +1,222 @@
-//! Helper type for processing process output and exit status in non-blocking way
-use crossbeam_channel::{unbounded, Receiver, SendError, Sender};
-use std::io::{self, prelude::*, BufReader};
-use std::process::{Child, Command, ExitStatus, Stdio};
-use std::sync::{Arc, Mutex};
-use std::thread::{self, JoinHandle};
-
-type ProcessHandle = JoinHandle<Result<(), SendError<Option<Output>>>>;
-type OutputReceiver = Receiver<Option<Output>>;
-type OutputSender = Sender<Option<Output>>;
-
-struct ProcessHandlers {
-    stdout: ProcessHandle,
-    stderr: ProcessHandle,
-    status: ProcessHandle,
+//! Run a command through [`Process`], streaming its output as lines or JSON events.
+//!
+//! ```text
+//! process [OPTIONS] [--] <COMMAND> [ARGS...]
+//! ```
+use misc::process::{Exit, JsonLinesEncoder, LogOptions, Output, ProcessBuilder, StdioPolicy};
+use std::io::{self, Write};
+use std::process::ExitCode;
+use std::time::Duration;
+
+const USAGE: &str = "\
+Usage: process [OPTIONS] [--] <COMMAND> [ARGS...]
+
+Options:
+  -t, --timeout <DURATION>  Kill the command after DURATION, e.g. 30, 1.5s, 500ms or 2m
+  -p, --prefix              Prefix lines with `out| ` or `err| `
+      --color <WHEN>        Colour stderr lines red: auto, always or never [default: auto]
+  -j, --json                Print every event as one JSON object per line
+      --log <PATH>          Append timestamped output to PATH
+  -e, --exit-code           Exit with the command's exit code, 128 + signal or 124 on timeout
+  -h, --help                Print this help";
+
+#[derive(Debug, Default, PartialEq)]
+struct Args {
+    timeout: Option<Duration>,
+    prefix: bool,
+    color: Color,
+    json: bool,
+    log: Option<String>,
+    exit_code: bool,
+    command: Vec<String>,
 }
 
-/// Process struct for handleing a [`Child`] in non-blocking way
-pub struct Process {
-    inner: Arc<Mutex<Child>>,
-    rx: OutputReceiver,
-    handlers: ProcessHandlers,
-    mtx: Sender<bool>,
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+enum Color {
+    #[default]
+    Auto,
+    Always,
+    Never,
 }
 
-impl Process {
-    /// Create new process from [`Child`]
-    pub fn new(command: &mut Command) -> io::Result<Process> {
-        let ((tx, rx), (mtx, mrx)) = (unbounded(), unbounded());
-
-        command.stdout(Stdio::piped());
-        command.stderr(Stdio::piped());
-        command.stdin(Stdio::null());
-
-        let mut process = command.spawn()?;
-
-        let stdout = process.stdout.take().unwrap();
-        let stderr = process.stderr.take().unwrap();
-        let inner = Arc::new(Mutex::new(process));
-
-        let handlers = ProcessHandlers {
-            stdout: spawn_reader(true, stdout, tx.clone(), mrx.clone(), mtx.clone()),
-            stderr: spawn_reader(false, stderr, tx.clone(), mrx.clone(), mtx.clone()),
-            status: spawn_status_thread(inner.clone(), tx.clone(), mrx.clone(), mtx.clone()),
-        };
-
-        Ok(Process {
-            inner,
-            rx,
-            mtx,
-            handlers,
-        })
-    }
-
-    /// Get iteratorable stream of outputs
-    pub fn stream(&mut self) -> OutputStream {
-        OutputStream {
-            rx: &mut self.rx,
-            exit: false,
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("process: {e}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(&args) {
+        Ok(exit) if args.exit_code => ExitCode::from(exit_code(&exit)),
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("process: {}: {e}", args.command[0]);
+            ExitCode::from(127)
         }
     }
+}
 
-    /// Block current thread until the process exist.
-    pub fn wait(&self) -> io::Result<ExitStatus> {
-        self.inner.lock().unwrap().wait()
+fn run(args: &Args) -> io::Result<Exit> {
+    let mut builder = ProcessBuilder::new(&args.command[0])
+        .args(&args.command[1..])
+        .stdin(StdioPolicy::Inherit);
+    if let Some(timeout) = args.timeout {
+        builder = builder.deadline(timeout);
     }
-
-    /// Kill running process and close all channels
-    pub fn kill(self) -> Option<()> {
-        self.mtx.send(true).unwrap();
-        self.handlers.stdout.join().unwrap().unwrap();
-        self.handlers.stderr.join().unwrap().unwrap();
-        self.handlers.status.join().unwrap().unwrap();
-        let mut child = match self.inner.lock() {
-            Ok(p) => p,
-            Err(e) => e.into_inner(),
-        };
-        child.kill().unwrap();
-
-        Some(())
+    if let Some(path) = &args.log {
+        builder = builder.log(LogOptions::combined(path).timestamps());
     }
-}
-
-/// OutputStream iterator
-pub struct OutputStream<'a> {
-    rx: &'a mut OutputReceiver,
-    exit: bool,
-}
-
-impl<'a> OutputStream<'a> {
-    /// No blocking equivalent of next
-    pub fn try_next(&mut self) -> Option<Output> {
-        if let Ok(Some(output)) = self.rx.try_recv() {
-            Some(output)
+    let process = builder.spawn()?;
+    let color = match args.color {
+        Color::Auto => unsafe { libc::isatty(libc::STDERR_FILENO) == 1 },
+        Color::Always => true,
+        Color::Never => false,
+    };
+
+    let (stdout, stderr) = (io::stdout(), io::stderr());
+    let mut encoder = JsonLinesEncoder::new(stdout.lock(), Some(process.id()));
+    let mut events = process.stream().ordered().events();
+    let closed = loop {
+        let Some(event) = events.next() else {
+            break false;
+        };
+        let written = if args.json {
+            encoder.encode(&event)
         } else {
-            None
+            match &event.output {
+                Output::Out(line) => match args.prefix {
+                    true => writeln!(stdout.lock(), "out| {line}"),
+                    false => writeln!(stdout.lock(), "{line}"),
+                },
+                Output::Err(line) => {
+                    let prefix = if args.prefix { "err| " } else { "" };
+                    match color {
+                        true => writeln!(stderr.lock(), "\x1b[31m{prefix}{line}\x1b[0m"),
+                        false => writeln!(stderr.lock(), "{prefix}{line}"),
+                    }
+                }
+                Output::Exit(Ok(exit)) if !exit.success() => {
+                    writeln!(stderr.lock(), "process: {} {exit}", args.command[0])
+                }
+                Output::Exit(_) => Ok(()),
+                output => writeln!(stderr.lock(), "{output}"),
+            }
+        };
+        // Our reader went away (e.g. piped into `head`), pass that on as the child would see it
+        if matches!(&written, Err(e) if e.kind() == io::ErrorKind::BrokenPipe) {
+            break true;
         }
+    };
+    drop(events);
+    match closed {
+        true => process.terminate_with(libc::SIGPIPE, Duration::from_secs(1)),
+        false => process.wait(),
     }
 }
 
-impl<'a> Iterator for OutputStream<'a> {
-    type Item = Output;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.exit {
-            None
-        } else if let Ok(Some(output)) = self.rx.recv() {
-            self.exit = matches!(output, Output::Exit(..));
-            Some(output)
-        } else {
-            None
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Option<Args>, String> {
+    let mut parsed = Args::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{name} needs a value"));
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-t" | "--timeout" => parsed.timeout = Some(parse_duration(&value(&arg)?)?),
+            "-p" | "--prefix" => parsed.prefix = true,
+            "--color" => {
+                parsed.color = match value(&arg)?.as_str() {
+                    "auto" => Color::Auto,
+                    "always" => Color::Always,
+                    "never" => Color::Never,
+                    other => return Err(format!("invalid --color `{other}`")),
+                }
+            }
+            "-j" | "--json" => parsed.json = true,
+            "--log" => parsed.log = Some(value(&arg)?),
+            "-e" | "--exit-code" => parsed.exit_code = true,
+            "--" => {
+                parsed.command.extend(args);
+                break;
+            }
+            flag if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(format!("unknown option `{flag}`"))
+            }
+            _ => {
+                parsed.command.push(arg);
+                parsed.command.extend(args);
+                break;
+            }
         }
     }
+    if parsed.command.is_empty() {
+        return Err("missing command".into());
+    }
+    Ok(Some(parsed))
 }
 
-/// Output produced by [`Process`] and [`OutputStream`]
-#[derive(Debug)]
-pub enum Output {
-    /// Source stdout
-    Out(String),
-    /// Source stderr or internal io::Error
-    Err(String),
-    /// Exit status
-    Exit(Result<Option<i32>, io::Error>),
+/// Seconds with an optional `ms`, `s` or `m` unit
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (number, scale) = if let Some(ms) = value.strip_suffix("ms") {
+        (ms, 0.001)
+    } else if let Some(s) = value.strip_suffix('s') {
+        (s, 1.0)
+    } else if let Some(m) = value.strip_suffix('m') {
+        (m, 60.0)
+    } else {
+        (value, 1.0)
+    };
+    number
+        .parse::<f64>()
+        .ok()
+        .and_then(|n| Duration::try_from_secs_f64(n * scale).ok())
+        .ok_or(format!("invalid duration `{value}`"))
 }
 
-impl std::fmt::Display for Output {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Output::Out(msg) => msg.fmt(f),
-            Output::Err(msg) => write!(f, "[Error] {msg}"),
-            Output::Exit(Ok(Some(code))) => code.fmt(f),
-            _ => Ok(()),
-        }
+/// Exit status to report for the command, following shell and `timeout(1)` conventions
+fn exit_code(exit: &Exit) -> u8 {
+    match (exit.timed_out(), exit.code, exit.signal) {
+        (true, ..) => 124,
+        (false, Some(code), _) => code as u8,
+        (false, None, Some(signal)) => 128u8.saturating_add(signal as u8),
+        (false, None, None) => 1,
     }
 }
 
-fn spawn_reader<R: Read + Send + 'static>(
-    is_stdout: bool,
-    out: R,
-    tx: OutputSender,
-    mrx: Receiver<bool>,
-    mtx: Sender<bool>,
-) -> ProcessHandle {
-    thread::spawn(move || {
-        let stdout_reader = BufReader::new(out);
-        let mut lines = stdout_reader.lines();
-        loop {
-            if let Ok(stop) = mrx.try_recv() {
-                if stop {
-                    mtx.send(stop).ok();
-                    break;
-                }
-            }
-            let output = match lines.next() {
-                Some(Ok(line)) if is_stdout => Output::Out(line),
-                Some(Ok(line)) => Output::Err(line),
-                Some(Err(e)) => Output::Err(e.to_string()),
-                _ => continue,
-            };
-
-            tx.send(Some(output))?;
-        }
-        Ok(())
-    })
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-fn spawn_status_thread(
-    shared_child: Arc<Mutex<Child>>,
-    tx: Sender<Option<Output>>,
-    mrx: Receiver<bool>,
-    mtx: Sender<bool>,
-) -> ProcessHandle {
-    thread::spawn(move || {
-        loop {
-            if let Ok(stop) = mrx.try_recv() {
-                if stop {
-                    mtx.send(stop).ok();
-                    break;
-                }
-            }
-            if let Ok(ref mut child) = shared_child.try_lock() {
-                match child.try_wait() {
-                    Err(err) => {
-                        tx.send(Some(Output::Exit(Err(err))))?;
-                        break;
-                    }
-                    Ok(Some(status)) => {
-                        tx.send(Some(Output::Exit(Ok(status.code()))))?;
-                        break;
-                    }
-                    Ok(None) => continue,
-                };
-            };
-        }
+    fn args(args: &[&str]) -> Result<Option<Args>, String> {
+        parse_args(args.iter().map(|a| a.to_string()))
+    }
 
-        Ok(())
-    })
-}
+    #[test]
+    fn parses_flags_before_command() {
+        let parsed = args(&["-t", "1.5s", "--json", "-e", "ls", "-la", "--color"])
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(parsed.timeout, Some(Duration::from_millis(1500)));
+        assert!(parsed.json && parsed.exit_code);
+        assert_eq!(parsed.command, ["ls", "-la", "--color"]);
+        assert_eq!(args(&["--", "-x"]).unwrap().unwrap().command, ["-x"]);
+        assert!(args(&["--bogus", "ls"]).is_err());
+        assert!(args(&["-t", "soon", "ls"]).is_err());
+        assert!(args(&["-p"]).is_err());
+        assert_eq!(args(&["--help"]), Ok(None));
+    }
 
-fn main() {
-    let mut process = Process::new(
-        Command::new("xcrun")
-            .arg("simctl")
-            .arg("launch")
-            .arg("--terminate-running-process")
-            .arg("--console")
-            .arg("booted")
-            .arg("tami5.Wordle"),
-    )
-    .unwrap();
-
-    for output in process.stream() {
-        println!("{output}")
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_duration("3"), Ok(Duration::from_secs(3)));
+        assert!(parse_duration("-1").is_err());
     }
 }
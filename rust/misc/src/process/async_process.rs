@@ -0,0 +1,234 @@
+//! Tokio-native equivalent of [`super::Process`]
+use super::{Exit, Output};
+use futures::Stream;
+use std::io;
+use std::pin::Pin;
+use std::process::Stdio;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+
+type StatusReceiver = watch::Receiver<Option<Result<Exit, (io::ErrorKind, String)>>>;
+type StatusSender = watch::Sender<Option<Result<Exit, (io::ErrorKind, String)>>>;
+
+/// Process struct for handling a [`Child`] inside a tokio runtime without blocking threads
+pub struct AsyncProcess {
+    id: Option<u32>,
+    rx: mpsc::UnboundedReceiver<Output>,
+    status: StatusReceiver,
+    kill: Option<oneshot::Sender<()>>,
+    exit: bool,
+}
+
+impl AsyncProcess {
+    /// Create new process from [`Command`]. Must be called within a tokio runtime.
+    pub fn new(command: &mut Command) -> io::Result<AsyncProcess> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (kill, kill_rx) = oneshot::channel();
+        let (status_tx, status) = watch::channel(None);
+
+        command.stdout(Stdio::piped());
+        command.stderr(Stdio::piped());
+        command.stdin(Stdio::null());
+
+        let mut child = command.spawn()?;
+
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+        let readers = [
+            spawn_reader(true, stdout, tx.clone()),
+            spawn_reader(false, stderr, tx.clone()),
+        ];
+
+        let id = child.id();
+        tokio::spawn(watch_status(child, readers, kill_rx, tx, status_tx));
+
+        Ok(AsyncProcess {
+            id,
+            rx,
+            status,
+            kill: Some(kill),
+            exit: false,
+        })
+    }
+
+    /// OS-assigned process identifier, None if the process was already reaped at spawn time.
+    pub fn id(&self) -> Option<u32> {
+        self.id
+    }
+
+    /// Get [`Stream`] of outputs
+    pub fn stream(&mut self) -> AsyncOutputStream<'_> {
+        AsyncOutputStream { process: self }
+    }
+
+    /// Wait until the process exits.
+    pub async fn wait(&self) -> io::Result<Exit> {
+        let mut status = self.status.clone();
+        loop {
+            if let Some(result) = status.borrow().as_ref() {
+                return result
+                    .clone()
+                    .map_err(|(kind, msg)| io::Error::new(kind, msg));
+            }
+            if status.changed().await.is_err() {
+                return Err(io::Error::other(
+                    "status task ended without reporting an exit status",
+                ));
+            }
+        }
+    }
+
+    /// Kill running process and wait for it to exit
+    pub async fn kill(&mut self) -> io::Result<Exit> {
+        if let Some(kill) = self.kill.take() {
+            kill.send(()).ok();
+        }
+        self.wait().await
+    }
+}
+
+/// Stream of [`Output`] produced by [`AsyncProcess`], ending after [`Output::Exit`]
+pub struct AsyncOutputStream<'a> {
+    process: &'a mut AsyncProcess,
+}
+
+impl<'a> Stream for AsyncOutputStream<'a> {
+    type Item = Output;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let process = &mut *self.process;
+        if process.exit {
+            return Poll::Ready(None);
+        }
+        match process.rx.poll_recv(cx) {
+            Poll::Ready(Some(output)) => {
+                process.exit = matches!(output, Output::Exit(..));
+                Poll::Ready(Some(output))
+            }
+            Poll::Ready(None) => {
+                process.exit = true;
+                Poll::Ready(None)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+fn spawn_reader<R: AsyncRead + Unpin + Send + 'static>(
+    is_stdout: bool,
+    out: R,
+    tx: mpsc::UnboundedSender<Output>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut reader = BufReader::new(out);
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            match reader.read_until(b'\n', &mut buf).await {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) => {
+                    tx.send(Output::Err(e.to_string())).ok();
+                    break;
+                }
+            }
+
+            // Same lines as `ReadMode::Lines`, invalid UTF-8 replaced instead of dropped
+            let line = buf.strip_suffix(b"\n").unwrap_or(&buf);
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let line = String::from_utf8_lossy(line).into_owned();
+            let output = match is_stdout {
+                true => Output::Out(line),
+                false => Output::Err(line),
+            };
+            if tx.send(output).is_err() {
+                break;
+            }
+        }
+    })
+}
+
+async fn watch_status(
+    mut child: Child,
+    readers: [JoinHandle<()>; 2],
+    kill: oneshot::Receiver<()>,
+    tx: mpsc::UnboundedSender<Output>,
+    status_tx: StatusSender,
+) {
+    let status = tokio::select! {
+        status = child.wait() => status,
+        Ok(()) = kill => match child.start_kill() {
+            Ok(()) => child.wait().await,
+            Err(e) => Err(e),
+        },
+    };
+
+    // Drain remaining output before reporting exit so it is the last item on the stream.
+    for reader in readers {
+        reader.await.ok();
+    }
+
+    let exit = status.map(Exit::from);
+    let output = match &exit {
+        Ok(exit) => Output::Exit(Ok(*exit)),
+        Err(e) => Output::Exit(Err(io::Error::new(e.kind(), e.to_string()))),
+    };
+    tx.send(output).ok();
+    status_tx
+        .send(Some(exit.map_err(|e| (e.kind(), e.to_string()))))
+        .ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn streams_output_then_exit() {
+        let mut process =
+            AsyncProcess::new(Command::new("sh").args(["-c", "echo out; echo err >&2; exit 3"]))
+                .unwrap();
+
+        let outputs: Vec<Output> = process.stream().collect().await;
+
+        assert!(outputs
+            .iter()
+            .any(|o| matches!(o, Output::Out(l) if l == "out")));
+        assert!(outputs
+            .iter()
+            .any(|o| matches!(o, Output::Err(l) if l == "err")));
+        assert!(matches!(
+            outputs.last(),
+            Some(Output::Exit(Ok(Exit { code: Some(3), .. })))
+        ));
+        assert_eq!(process.wait().await.unwrap().code, Some(3));
+    }
+
+    #[tokio::test]
+    async fn kill_stops_running_process() {
+        let mut process = AsyncProcess::new(Command::new("sleep").arg("30")).unwrap();
+
+        let exit = process.kill().await.unwrap();
+
+        assert_eq!(exit.signal, Some(libc::SIGKILL));
+        assert!(matches!(
+            process.stream().next().await,
+            Some(Output::Exit(Ok(Exit { code: None, .. })))
+        ));
+    }
+
+    #[tokio::test]
+    async fn replaces_invalid_utf8() {
+        let mut process =
+            AsyncProcess::new(Command::new("sh").args(["-c", "printf 'a\\377b\\r\\nok\\n'"]))
+                .unwrap();
+
+        let outputs: Vec<String> = process.stream().map(|o| o.to_string()).collect().await;
+
+        assert_eq!(outputs, ["a\u{fffd}b", "ok", "exited with code 0"]);
+    }
+}
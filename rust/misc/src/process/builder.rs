@@ -0,0 +1,267 @@
+//! Builder configuring the command, environment and limits of a [`Process`]
+use super::{
+    sys, Buffer, Capture, DropPolicy, InputScript, LogOptions, Process, ProcessGroup,
+    ProcessOptions, ProgressOptions, PtyOptions, ReadMode, StdioPolicy,
+};
+use std::ffi::OsStr;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+/// Resource limit applied to the child right before exec. Soft and hard limits are both set,
+/// so the child cannot raise them again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    /// CPU time in seconds, the child receives SIGXCPU then SIGKILL once exceeded
+    CpuSeconds(u64),
+    /// Virtual address space in bytes
+    AddressSpace(u64),
+    /// Number of open file descriptors
+    OpenFiles(u64),
+    /// Largest file the child may create, in bytes
+    FileSize(u64),
+    /// Size of core dumps in bytes, 0 disables them
+    CoreSize(u64),
+    /// Number of processes for the child's user
+    Processes(u64),
+}
+
+impl Limit {
+    fn resource(self) -> (sys::Resource, u64) {
+        match self {
+            Limit::CpuSeconds(value) => (libc::RLIMIT_CPU, value),
+            Limit::AddressSpace(value) => (libc::RLIMIT_AS, value),
+            Limit::OpenFiles(value) => (libc::RLIMIT_NOFILE, value),
+            Limit::FileSize(value) => (libc::RLIMIT_FSIZE, value),
+            Limit::CoreSize(value) => (libc::RLIMIT_CORE, value),
+            Limit::Processes(value) => (libc::RLIMIT_NPROC, value),
+        }
+    }
+}
+
+/// Builder for [`Process`] with explicit environment, working directory, stdio and limits.
+///
+/// Unlike [`Process::new`], nothing about the command is overridden behind the caller's back:
+/// every stdio stream follows the configured [`StdioPolicy`].
+pub struct ProcessBuilder {
+    command: Command,
+    limits: Vec<Limit>,
+    options: ProcessOptions,
+}
+
+impl ProcessBuilder {
+    pub fn new(program: impl AsRef<OsStr>) -> Self {
+        Self {
+            command: Command::new(program),
+            limits: Vec::new(),
+            options: ProcessOptions::default(),
+        }
+    }
+
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Self {
+        self.command.arg(arg);
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.command.args(args);
+        self
+    }
+
+    /// Add or override an environment variable
+    pub fn env(mut self, key: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> Self {
+        self.command.env(key, value);
+        self
+    }
+
+    /// Remove an environment variable
+    pub fn env_remove(mut self, key: impl AsRef<OsStr>) -> Self {
+        self.command.env_remove(key);
+        self
+    }
+
+    /// Start from an empty environment instead of the parent's
+    pub fn env_clear(mut self) -> Self {
+        self.command.env_clear();
+        self
+    }
+
+    /// Copy a variable from the parent environment, typically after [`Self::env_clear`]
+    pub fn env_inherit(mut self, key: impl AsRef<OsStr>) -> Self {
+        if let Some(value) = std::env::var_os(key.as_ref()) {
+            self.command.env(key, value);
+        }
+        self
+    }
+
+    pub fn current_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.command.current_dir(dir);
+        self
+    }
+
+    pub fn stdin(mut self, policy: StdioPolicy) -> Self {
+        self.options.stdin = policy;
+        self
+    }
+
+    pub fn stdout(mut self, policy: StdioPolicy) -> Self {
+        self.options.stdout = policy;
+        self
+    }
+
+    pub fn stderr(mut self, policy: StdioPolicy) -> Self {
+        self.options.stderr = policy;
+        self
+    }
+
+    /// Apply a resource limit before exec, replacing an earlier limit on the same resource
+    pub fn limit(mut self, limit: Limit) -> Self {
+        let resource = limit.resource().0;
+        self.limits.retain(|l| l.resource().0 != resource);
+        self.limits.push(limit);
+        self
+    }
+
+    pub fn input(mut self, input: InputScript) -> Self {
+        self.options.input = Some(input);
+        self
+    }
+
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.options.deadline = Some(deadline);
+        self
+    }
+
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.options.idle_timeout = Some(timeout);
+        self
+    }
+
+    pub fn group(mut self, group: ProcessGroup) -> Self {
+        self.options.group = group;
+        self
+    }
+
+    pub fn pty(mut self, pty: PtyOptions) -> Self {
+        self.options.pty = Some(pty);
+        self
+    }
+
+    pub fn read_mode(mut self, mode: ReadMode) -> Self {
+        self.options.read_mode = mode;
+        self
+    }
+
+    /// Bound unread output, see [`Buffer`]
+    pub fn buffer(mut self, buffer: Buffer) -> Self {
+        self.options.buffer = buffer;
+        self
+    }
+
+    /// Keep the last output of each stream, see [`Process::tail`]
+    pub fn capture(mut self, capture: Capture) -> Self {
+        self.options.capture = Some(capture);
+        self
+    }
+
+    /// Mirror stdout and stderr to log files, see [`LogOptions`]
+    pub fn log(mut self, log: LogOptions) -> Self {
+        self.options.log = Some(log);
+        self
+    }
+
+    /// What dropping the [`Process`] does with a running child, see [`DropPolicy`]
+    pub fn on_drop(mut self, policy: DropPolicy) -> Self {
+        self.options.on_drop = policy;
+        self
+    }
+
+    /// Report progress found in stdout and stderr, see [`ProgressOptions`]
+    pub fn progress(mut self, progress: ProgressOptions) -> Self {
+        self.options.progress = Some(progress);
+        self
+    }
+
+    /// Spawn the configured command
+    pub fn spawn(mut self) -> io::Result<Process> {
+        if !self.limits.is_empty() {
+            let limits: Vec<_> = self.limits.iter().map(|l| l.resource()).collect();
+            unsafe {
+                self.command.pre_exec(move || {
+                    for &(resource, value) in &limits {
+                        sys::setrlimit(resource, value)?;
+                    }
+                    Ok(())
+                });
+            }
+        }
+        Process::with_options(&mut self.command, self.options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::Output;
+
+    fn lines(process: Process) -> Vec<String> {
+        process.stream().map(|o| o.to_string()).collect()
+    }
+
+    #[test]
+    fn configures_env_and_cwd() {
+        // PATH is always set for the test binary, mutating our own environment would race
+        // with tests spawning children concurrently
+        let path = std::env::var("PATH").unwrap();
+        let process = ProcessBuilder::new("/bin/sh")
+            .args(["-c", "echo \"$PATH $ADDED ${HOME:-none}\"; pwd"])
+            .env_clear()
+            .env_inherit("PATH")
+            .env("ADDED", "added")
+            .current_dir("/")
+            .spawn()
+            .unwrap();
+
+        assert_eq!(
+            lines(process),
+            [
+                format!("{path} added none"),
+                "/".into(),
+                "exited with code 0".into()
+            ]
+        );
+    }
+
+    #[test]
+    fn applies_resource_limits() {
+        let process = ProcessBuilder::new("sh")
+            .args(["-c", "ulimit -n; ulimit -t"])
+            .limit(Limit::OpenFiles(64))
+            .limit(Limit::CpuSeconds(5))
+            .limit(Limit::CpuSeconds(7))
+            .spawn()
+            .unwrap();
+
+        assert_eq!(lines(process), ["64", "7", "exited with code 0"]);
+    }
+
+    #[test]
+    fn stdio_policy_skips_unpiped_streams() {
+        let process = ProcessBuilder::new("sh")
+            .args(["-c", "echo out; echo err >&2"])
+            .stdout(StdioPolicy::Null)
+            .spawn()
+            .unwrap();
+
+        let outputs: Vec<Output> = process.stream().collect();
+
+        assert_eq!(outputs.len(), 2);
+        assert!(matches!(&outputs[0], Output::Err(l) if l == "err"));
+    }
+}
@@ -0,0 +1,177 @@
+//! Post-mortem capture of the last output of a [`super::Process`]
+use super::{Output, ReadMode};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// How much of each stream [`super::Process`] keeps for [`super::Process::tail`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capture {
+    /// Keep the last this many lines per stream, or reads with [`ReadMode::Chunks`]
+    Lines(usize),
+    /// Keep the last this many bytes per stream, line endings not counted
+    Bytes(usize),
+}
+
+/// Ring buffers of the last stdout and stderr items, filled as they are read whether or not
+/// the stream is consumed
+pub(super) struct CaptureBuffer {
+    limit: Capture,
+    /// Appended after each item when joining, lines lost their line ending when read
+    separator: &'static [u8],
+    rings: Mutex<Rings>,
+}
+
+#[derive(Default)]
+struct Rings {
+    /// Read order across both streams
+    next: u64,
+    stdout: Ring,
+    stderr: Ring,
+}
+
+#[derive(Default)]
+struct Ring {
+    items: VecDeque<(u64, Vec<u8>)>,
+    bytes: usize,
+}
+
+impl Ring {
+    fn push(&mut self, seq: u64, item: Vec<u8>, limit: Capture) {
+        self.bytes += item.len();
+        self.items.push_back((seq, item));
+        match limit {
+            Capture::Lines(max) => {
+                while self.items.len() > max {
+                    self.pop();
+                }
+            }
+            Capture::Bytes(max) => {
+                while self.bytes > max {
+                    let excess = self.bytes - max;
+                    match self.items.front_mut() {
+                        Some((_, front)) if front.len() > excess => {
+                            front.drain(..excess);
+                            self.bytes -= excess;
+                        }
+                        _ => self.pop(),
+                    }
+                }
+            }
+        }
+    }
+
+    fn pop(&mut self) {
+        if let Some((_, item)) = self.items.pop_front() {
+            self.bytes -= item.len();
+        }
+    }
+
+    fn text(&self, separator: &[u8]) -> String {
+        let mut text = Vec::with_capacity(self.bytes + self.items.len() * separator.len());
+        for (_, item) in &self.items {
+            text.extend_from_slice(item);
+            text.extend_from_slice(separator);
+        }
+        String::from_utf8_lossy(&text).into_owned()
+    }
+}
+
+impl CaptureBuffer {
+    pub fn new(limit: Capture, mode: ReadMode) -> Self {
+        Self {
+            limit,
+            separator: match mode {
+                ReadMode::Lines | ReadMode::Bytes => b"\n",
+                ReadMode::Chunks(_) => b"",
+            },
+            rings: Mutex::default(),
+        }
+    }
+
+    /// Keep `output` if it is stdout or stderr
+    pub fn record(&self, output: &Output) {
+        let (stdout, item) = match output {
+            Output::Out(line) => (true, line.as_bytes()),
+            Output::Err(line) => (false, line.as_bytes()),
+            Output::OutBytes(bytes) => (true, &bytes[..]),
+            Output::ErrBytes(bytes) => (false, &bytes[..]),
+            _ => return,
+        };
+        let mut rings = self.rings.lock().unwrap_or_else(|e| e.into_inner());
+        let seq = rings.next;
+        rings.next += 1;
+        let ring = match stdout {
+            true => &mut rings.stdout,
+            false => &mut rings.stderr,
+        };
+        ring.push(seq, item.to_vec(), self.limit);
+    }
+
+    /// Last `n` kept items of both streams in the order they were read
+    pub fn tail(&self, n: usize) -> Vec<Output> {
+        let rings = self.rings.lock().unwrap_or_else(|e| e.into_inner());
+        let stdout = rings
+            .stdout
+            .items
+            .iter()
+            .map(|(seq, item)| (*seq, true, item));
+        let stderr = rings
+            .stderr
+            .items
+            .iter()
+            .map(|(seq, item)| (*seq, false, item));
+        let mut items: Vec<_> = stdout.chain(stderr).collect();
+        items.sort_unstable_by_key(|(seq, ..)| *seq);
+        items[items.len().saturating_sub(n)..]
+            .iter()
+            .map(|(_, stdout, item)| {
+                let text = String::from_utf8_lossy(item).into_owned();
+                match stdout {
+                    true => Output::Out(text),
+                    false => Output::Err(text),
+                }
+            })
+            .collect()
+    }
+
+    pub fn stdout_text(&self) -> String {
+        let rings = self.rings.lock().unwrap_or_else(|e| e.into_inner());
+        rings.stdout.text(self.separator)
+    }
+
+    pub fn stderr_text(&self) -> String {
+        let rings = self.rings.lock().unwrap_or_else(|e| e.into_inner());
+        rings.stderr.text(self.separator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_last_lines_per_stream() {
+        let capture = CaptureBuffer::new(Capture::Lines(2), ReadMode::Lines);
+        for i in 0..5 {
+            capture.record(&Output::Out(format!("out {i}")));
+        }
+        capture.record(&Output::Err("err".into()));
+        capture.record(&Output::Dropped(3));
+
+        assert_eq!(capture.stdout_text(), "out 3\nout 4\n");
+        assert_eq!(capture.stderr_text(), "err\n");
+        assert!(matches!(
+            &capture.tail(2)[..],
+            [Output::Out(a), Output::Err(b)] if a == "out 4" && b == "err"
+        ));
+    }
+
+    #[test]
+    fn trims_to_byte_limit() {
+        let capture = CaptureBuffer::new(Capture::Bytes(5), ReadMode::Chunks(4));
+        capture.record(&Output::OutBytes(b"abcd".to_vec()));
+        capture.record(&Output::OutBytes(b"efg".to_vec()));
+
+        assert_eq!(capture.stdout_text(), "cdefg");
+    }
+}
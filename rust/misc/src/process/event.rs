@@ -0,0 +1,248 @@
+//! Timestamped and sequenced [`Output`] events
+use super::capture::CaptureBuffer;
+use super::tee::Tee;
+use super::{Buffer, Output, OutputReceiver, Overflow};
+use crossbeam_channel::{bounded, unbounded, Receiver, SendError, Sender};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// [`Output`] together with when it was read and its position across all streams
+#[derive(Debug)]
+pub struct Event {
+    /// Sequence number shared by stdout, stderr and exit, assigned as the event was read
+    pub seq: u64,
+    /// Monotonic time the event was read
+    pub at: Instant,
+    /// Time since the process was spawned
+    pub elapsed: Duration,
+    pub output: Output,
+}
+
+/// Source of sequence numbers and timestamps shared by every sender of a process
+struct Clock {
+    started: Instant,
+    /// Next sequence number, the clock is read under the same lock so `at` follows `seq`
+    next: Mutex<u64>,
+}
+
+/// Sending half of a process event channel, stamping each [`Output`] as it is sent
+#[derive(Clone)]
+pub(super) struct OutputSender {
+    tx: Sender<Event>,
+    clock: Arc<Clock>,
+    lossy: Option<Arc<Lossy>>,
+    capture: Option<Arc<CaptureBuffer>>,
+    tee: Option<Arc<Tee>>,
+}
+
+/// Overflow handling of a bounded channel that drops events instead of blocking
+struct Lossy {
+    /// Receiving side used to evict the oldest event, only for [`Overflow::DropOldest`]. It
+    /// keeps the channel connected, so readers drain the child until it exits even after the
+    /// [`super::Process`] was dropped.
+    evict: Option<Receiver<Event>>,
+    /// Events dropped since the last [`Output::Dropped`]. Held across stamping and sending so
+    /// the channel stays in sequence order.
+    dropped: Mutex<u64>,
+}
+
+impl OutputSender {
+    pub fn new(tx: Sender<Event>) -> Self {
+        Self {
+            tx,
+            clock: Arc::new(Clock {
+                started: Instant::now(),
+                next: Mutex::new(0),
+            }),
+            lossy: None,
+            capture: None,
+            tee: None,
+        }
+    }
+
+    /// Create a channel sized and drained according to `buffer`
+    pub fn channel(buffer: Buffer) -> (Self, OutputReceiver) {
+        let (overflow, (tx, rx)) = match buffer {
+            Buffer::Unbounded => (Overflow::Block, unbounded()),
+            // Leave room for a dropped notice next to the event that follows it
+            Buffer::Bounded { capacity, overflow } if overflow != Overflow::Block => {
+                (overflow, bounded(capacity.max(2)))
+            }
+            Buffer::Bounded { capacity, overflow } => (overflow, bounded(capacity)),
+        };
+        let lossy = match overflow {
+            Overflow::Block => None,
+            Overflow::DropOldest => Some(Some(rx.clone())),
+            Overflow::DropNewest => Some(None),
+        };
+        let sender = Self {
+            lossy: lossy.map(|evict| {
+                Arc::new(Lossy {
+                    evict,
+                    dropped: Mutex::new(0),
+                })
+            }),
+            ..Self::new(tx)
+        };
+        (sender, rx)
+    }
+
+    /// Also record stdout and stderr into `capture`, before any of it can be dropped
+    pub fn capture(self, capture: Option<Arc<CaptureBuffer>>) -> Self {
+        Self { capture, ..self }
+    }
+
+    /// Also write stdout and stderr to log files, reporting the first failure as [`Output::Err`]
+    pub fn tee(self, tee: Option<Arc<Tee>>) -> Self {
+        Self { tee, ..self }
+    }
+
+    /// Whether events reach the channel in sequence order, which holds for lossy channels
+    pub fn sequential(&self) -> bool {
+        self.lossy.is_some()
+    }
+
+    /// Stamp and send `output`, failing once the receiving [`super::Process`] was dropped
+    pub fn send(&self, output: Output) -> Result<(), SendError<()>> {
+        if let Some(capture) = &self.capture {
+            capture.record(&output);
+        }
+        if let Some(tee) = &self.tee {
+            if let Err(e) = tee.write(&output) {
+                self.send(Output::Err(e.to_string()))?;
+            }
+        }
+        match &self.lossy {
+            Some(lossy) => self.send_lossy(lossy, output),
+            None => self.tx.send(self.stamp(output)).map_err(|_| SendError(())),
+        }
+    }
+
+    fn stamp(&self, output: Output) -> Event {
+        let mut next = self.clock.next.lock().unwrap_or_else(|e| e.into_inner());
+        let (seq, at) = (*next, Instant::now());
+        *next += 1;
+        drop(next);
+        Event {
+            seq,
+            at,
+            elapsed: at - self.clock.started,
+            output,
+        }
+    }
+
+    /// Send without blocking, reporting what did not fit as one [`Output::Dropped`] ahead of
+    /// the next event that does.
+    fn send_lossy(&self, lossy: &Lossy, output: Output) -> Result<(), SendError<()>> {
+        let mut dropped = lossy.dropped.lock().unwrap_or_else(|e| e.into_inner());
+        let capacity = self.tx.capacity().unwrap_or(usize::MAX);
+        // The exit ends the stream, so it waits for room instead of being dropped
+        let keep = matches!(output, Output::Exit(_));
+        // Only the consumer takes events out meanwhile, so room made here stays available
+        while self.tx.len() + 1 + usize::from(*dropped > 0) > capacity {
+            match &lossy.evict {
+                Some(evict) => match evict.try_recv().map(|e| e.output) {
+                    Ok(Output::Dropped(count)) => *dropped += count,
+                    Ok(_) => *dropped += 1,
+                    Err(_) => {}
+                },
+                None if keep => break,
+                None => {
+                    *dropped += 1;
+                    return Ok(());
+                }
+            }
+        }
+        if *dropped > 0 {
+            let notice = self.stamp(Output::Dropped(std::mem::take(&mut *dropped)));
+            self.tx.send(notice).map_err(|_| SendError(()))?;
+        }
+        self.tx.send(self.stamp(output)).map_err(|_| SendError(()))
+    }
+}
+
+/// Buffer releasing events strictly by sequence number.
+///
+/// Reader threads stamp an event before sending it, so two threads can deliver their events
+/// to the channel in the opposite order. Holding back later events until the gap is filled
+/// restores the order in which output was actually read.
+pub(super) struct Reorder {
+    next: u64,
+    pending: BTreeMap<u64, Event>,
+}
+
+impl Reorder {
+    /// Start releasing at sequence number `next`, the first not yielded yet
+    pub fn new(next: u64) -> Self {
+        Self {
+            next,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, event: Event) {
+        self.pending.insert(event.seq, event);
+    }
+
+    /// Next event in sequence, if it already arrived. Events behind `next`, which an unordered
+    /// stream skipped over earlier, are released right away.
+    pub fn pop(&mut self) -> Option<Event> {
+        let (&seq, _) = self.pending.first_key_value()?;
+        if seq > self.next {
+            return None;
+        }
+        let (_, event) = self.pending.pop_first()?;
+        self.next = self.next.max(seq + 1);
+        Some(event)
+    }
+
+    /// Events received but not released yet
+    pub fn into_pending(self) -> impl Iterator<Item = Event> {
+        self.pending.into_values()
+    }
+
+    /// Oldest buffered event regardless of gaps, once no more events can arrive
+    pub fn drain(&mut self) -> Option<Event> {
+        let (_, event) = self.pending.pop_first()?;
+        self.next = event.seq + 1;
+        Some(event)
+    }
+}
+
+/// Where the streams of one source left off, shared by every [`super::OutputStream`] created
+/// from it
+#[derive(Default)]
+pub(super) struct Cursor {
+    /// Sequence number following the last yielded event
+    position: AtomicU64,
+    /// Events taken off the channel by an ordered stream that was dropped before yielding them
+    pending: Mutex<Vec<Event>>,
+}
+
+impl Cursor {
+    pub fn position(&self) -> u64 {
+        self.position.load(Ordering::Relaxed)
+    }
+
+    pub fn yielded(&self, event: &Event) {
+        self.position.fetch_max(event.seq + 1, Ordering::Relaxed);
+    }
+
+    /// Oldest pending event
+    pub fn pop(&self) -> Option<Event> {
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        let (index, _) = pending.iter().enumerate().min_by_key(|(_, e)| e.seq)?;
+        Some(pending.swap_remove(index))
+    }
+
+    pub fn take(&self) -> Vec<Event> {
+        std::mem::take(&mut *self.pending.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    pub fn put_back(&self, events: impl IntoIterator<Item = Event>) {
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        pending.extend(events);
+    }
+}
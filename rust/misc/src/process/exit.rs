@@ -0,0 +1,207 @@
+//! Exit details reported by [`super::Output::Exit`]
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+/// How a process finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Exit {
+    /// Exit code, None when the process was terminated by a signal
+    pub code: Option<i32>,
+    /// Signal that terminated the process
+    pub signal: Option<i32>,
+    /// Whether the terminating signal produced a core dump
+    pub core_dumped: bool,
+    /// Why the process stopped
+    pub reason: ExitReason,
+    /// Resources consumed by the process, when it was reaped by [`super::Process`]
+    pub usage: Option<ResourceUsage>,
+}
+
+/// Resources consumed by a finished process
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    /// CPU time spent in user mode
+    pub user_time: Duration,
+    /// CPU time spent in the kernel
+    pub system_time: Duration,
+    /// Peak resident set size in bytes
+    pub max_rss: u64,
+    /// Context switches caused by the process waiting, e.g. on IO
+    pub voluntary_context_switches: u64,
+    /// Context switches forced by the scheduler
+    pub involuntary_context_switches: u64,
+    /// Wall-clock time from spawn until the process was reaped
+    pub wall_time: Duration,
+}
+
+impl ResourceUsage {
+    pub(crate) fn new(usage: &libc::rusage, wall_time: Duration) -> Self {
+        let time = |tv: libc::timeval| {
+            Duration::from_secs(tv.tv_sec as u64) + Duration::from_micros(tv.tv_usec as u64)
+        };
+        // Linux reports the peak RSS in kilobytes, macOS in bytes.
+        let rss_unit = if cfg!(target_os = "macos") { 1 } else { 1024 };
+        Self {
+            user_time: time(usage.ru_utime),
+            system_time: time(usage.ru_stime),
+            max_rss: usage.ru_maxrss as u64 * rss_unit,
+            voluntary_context_switches: usage.ru_nvcsw as u64,
+            involuntary_context_switches: usage.ru_nivcsw as u64,
+            wall_time,
+        }
+    }
+
+    /// Total CPU time, user and system
+    pub fn cpu_time(&self) -> Duration {
+        self.user_time + self.system_time
+    }
+}
+
+/// Why a process stopped running
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(tag = "type", content = "limit", rename_all = "snake_case")]
+pub enum ExitReason {
+    /// The process exited or was killed on request
+    #[default]
+    Exited,
+    /// The process outlived its deadline and was killed
+    Deadline(Duration),
+    /// The process produced no output for the given duration and was killed
+    Idle(Duration),
+}
+
+impl Exit {
+    pub(crate) fn new(status: ExitStatus, reason: ExitReason) -> Self {
+        Self {
+            code: status.code(),
+            signal: status.signal(),
+            core_dumped: status.core_dumped(),
+            reason,
+            usage: None,
+        }
+    }
+
+    /// Name of the terminating signal, e.g. `SIGSEGV`
+    pub fn signal_name(&self) -> Option<&'static str> {
+        self.signal.and_then(signal_name)
+    }
+
+    /// Whether the process exited on its own with code 0
+    pub fn success(&self) -> bool {
+        self.code == Some(0) && self.reason == ExitReason::Exited
+    }
+
+    /// Whether the process was killed by a deadline or idle timeout
+    pub fn timed_out(&self) -> bool {
+        self.reason != ExitReason::Exited
+    }
+}
+
+impl From<ExitStatus> for Exit {
+    fn from(status: ExitStatus) -> Self {
+        Self::new(status, ExitReason::Exited)
+    }
+}
+
+impl fmt::Display for Exit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.code, self.signal) {
+            (Some(code), _) => write!(f, "exited with code {code}")?,
+            (None, Some(signal)) => match signal_name(signal) {
+                Some(name) => write!(f, "terminated by {name} (signal {signal})")?,
+                None => write!(f, "terminated by signal {signal}")?,
+            },
+            (None, None) => f.write_str("exited with unknown status")?,
+        }
+        if self.core_dumped {
+            f.write_str(" (core dumped)")?;
+        }
+        match self.reason {
+            ExitReason::Exited => Ok(()),
+            ExitReason::Deadline(limit) => write!(f, " after deadline of {limit:?}"),
+            ExitReason::Idle(limit) => write!(f, " after {limit:?} without output"),
+        }
+    }
+}
+
+/// Conventional name of a Unix signal number
+pub fn signal_name(signal: i32) -> Option<&'static str> {
+    Some(match signal {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGILL => "SIGILL",
+        libc::SIGTRAP => "SIGTRAP",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGUSR1 => "SIGUSR1",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGUSR2 => "SIGUSR2",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGALRM => "SIGALRM",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGCHLD => "SIGCHLD",
+        libc::SIGCONT => "SIGCONT",
+        libc::SIGSTOP => "SIGSTOP",
+        libc::SIGTSTP => "SIGTSTP",
+        libc::SIGTTIN => "SIGTTIN",
+        libc::SIGTTOU => "SIGTTOU",
+        libc::SIGURG => "SIGURG",
+        libc::SIGXCPU => "SIGXCPU",
+        libc::SIGXFSZ => "SIGXFSZ",
+        libc::SIGVTALRM => "SIGVTALRM",
+        libc::SIGPROF => "SIGPROF",
+        libc::SIGWINCH => "SIGWINCH",
+        libc::SIGIO => "SIGIO",
+        libc::SIGSYS => "SIGSYS",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exit(raw: i32) -> Exit {
+        Exit::from(ExitStatus::from_raw(raw))
+    }
+
+    #[test]
+    fn display_exit_code() {
+        assert_eq!(exit(3 << 8).to_string(), "exited with code 3");
+    }
+
+    #[test]
+    fn display_signal_and_core_dump() {
+        let exit = exit(libc::SIGSEGV | 0x80);
+
+        assert_eq!(exit.signal, Some(libc::SIGSEGV));
+        assert_eq!(exit.signal_name(), Some("SIGSEGV"));
+        assert!(exit.core_dumped);
+        assert_eq!(
+            exit.to_string(),
+            format!(
+                "terminated by SIGSEGV (signal {}) (core dumped)",
+                libc::SIGSEGV
+            )
+        );
+    }
+
+    #[test]
+    fn display_timeout_reason() {
+        let exit = Exit::new(
+            ExitStatus::from_raw(libc::SIGKILL),
+            ExitReason::Idle(Duration::from_secs(2)),
+        );
+
+        assert_eq!(
+            exit.to_string(),
+            "terminated by SIGKILL (signal 9) after 2s without output"
+        );
+    }
+}
@@ -0,0 +1,226 @@
+//! Expect-style matching on [`super::Process`] output
+use super::{Exit, Output, OutputStream, Process, Wait};
+use regex::Regex;
+use std::collections::HashMap;
+use std::io;
+use std::time::{Duration, Instant};
+
+/// Line matched by [`OutputStream::expect`]
+#[derive(Debug)]
+pub struct Match {
+    /// The whole stdout or stderr line the pattern matched in
+    pub line: String,
+    /// Whether the line came from stderr
+    pub stderr: bool,
+    /// Capture groups by index, 0 being the whole match
+    pub captures: Vec<Option<String>>,
+    /// Named capture groups that participated in the match
+    pub named: HashMap<String, String>,
+    /// Output consumed before the matching line
+    pub before: Vec<Output>,
+}
+
+impl Match {
+    /// Capture group `index`, 0 being the whole match
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.captures.get(index)?.as_deref()
+    }
+
+    /// Named capture group
+    pub fn name(&self, name: &str) -> Option<&str> {
+        self.named.get(name).map(String::as_str)
+    }
+}
+
+impl<'a> OutputStream<'a> {
+    /// Consume output until a stdout or stderr line matches `pattern`.
+    ///
+    /// Fails with [`io::ErrorKind::TimedOut`] once `timeout` passed and with
+    /// [`io::ErrorKind::UnexpectedEof`] when the process exits first.
+    pub fn expect(&mut self, pattern: &Regex, timeout: Duration) -> io::Result<Match> {
+        self.expect_any(std::slice::from_ref(pattern), timeout)
+            .map(|(_, found)| found)
+    }
+
+    /// Consume output until a line matches any of `patterns`, returning the index of the
+    /// first pattern that matched together with the [`Match`].
+    pub fn expect_any(
+        &mut self,
+        patterns: &[Regex],
+        timeout: Duration,
+    ) -> io::Result<(usize, Match)> {
+        let deadline = Instant::now() + timeout;
+        let mut before = Vec::new();
+        loop {
+            let output = match self.recv(Wait::Until(deadline)) {
+                Ok(Some(event)) => event.output,
+                Ok(None) => return Err(ended(patterns, None)),
+                Err(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("no line matched {} within {timeout:?}", describe(patterns)),
+                    ))
+                }
+            };
+            let (line, stderr) = match &output {
+                Output::Out(line) => (line.clone(), false),
+                Output::Err(line) => (line.clone(), true),
+                Output::OutBytes(bytes) => (String::from_utf8_lossy(bytes).into_owned(), false),
+                Output::ErrBytes(bytes) => (String::from_utf8_lossy(bytes).into_owned(), true),
+                Output::Exit(exit) => return Err(ended(patterns, exit.as_ref().ok())),
+                _ => {
+                    before.push(output);
+                    continue;
+                }
+            };
+            for (index, pattern) in patterns.iter().enumerate() {
+                if let Some(captures) = pattern.captures(&line) {
+                    let named = pattern
+                        .capture_names()
+                        .flatten()
+                        .filter_map(|name| Some((name.to_string(), captures.name(name)?)))
+                        .map(|(name, m)| (name, m.as_str().to_string()))
+                        .collect();
+                    let captures = captures
+                        .iter()
+                        .map(|m| m.map(|m| m.as_str().to_string()))
+                        .collect();
+                    let found = Match {
+                        line,
+                        stderr,
+                        captures,
+                        named,
+                        before,
+                    };
+                    return Ok((index, found));
+                }
+            }
+            before.push(output);
+        }
+    }
+
+    /// Consume output until the process exits
+    pub fn expect_exit(&mut self, timeout: Duration) -> io::Result<Exit> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.recv(Wait::Until(deadline)) {
+                Ok(Some(event)) => {
+                    if let Output::Exit(exit) = event.output {
+                        return exit;
+                    }
+                }
+                Ok(None) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "output stream ended without an exit event",
+                    ))
+                }
+                Err(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("process still running after {timeout:?}"),
+                    ))
+                }
+            }
+        }
+    }
+}
+
+impl Process {
+    /// See [`OutputStream::expect`]
+    pub fn expect(&self, pattern: &Regex, timeout: Duration) -> io::Result<Match> {
+        self.expect_any(std::slice::from_ref(pattern), timeout)
+            .map(|(_, found)| found)
+    }
+
+    /// See [`OutputStream::expect_any`]. Reads an [`OutputStream::ordered`] stream, so output
+    /// read after the matching line is left for the next call instead of being consumed.
+    pub fn expect_any(&self, patterns: &[Regex], timeout: Duration) -> io::Result<(usize, Match)> {
+        self.stream().ordered().expect_any(patterns, timeout)
+    }
+
+    /// Consume output until the process exits. Also succeeds when the exit event was already
+    /// consumed, e.g. by a failed [`Process::expect`].
+    pub fn expect_exit(&self, timeout: Duration) -> io::Result<Exit> {
+        match self.stream().expect_exit(timeout) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => self.wait(),
+            result => result,
+        }
+    }
+}
+
+fn describe(patterns: &[Regex]) -> String {
+    let patterns: Vec<String> = patterns.iter().map(|p| format!("`{p}`")).collect();
+    patterns.join(" or ")
+}
+
+fn ended(patterns: &[Regex], exit: Option<&Exit>) -> io::Error {
+    let msg = match exit {
+        Some(exit) => format!("process {exit} before matching {}", describe(patterns)),
+        None => format!("process ended before matching {}", describe(patterns)),
+    };
+    io::Error::new(io::ErrorKind::UnexpectedEof, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::{ProcessOptions, StdioPolicy};
+    use std::process::Command;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn interactive(script: &str) -> Process {
+        let options = ProcessOptions {
+            stdin: StdioPolicy::Piped,
+            ..Default::default()
+        };
+        Process::with_options(Command::new("sh").args(["-c", script]), options).unwrap()
+    }
+
+    #[test]
+    fn scripts_interactive_prompt() {
+        let process = interactive(
+            "echo banner; echo 'name?'; read name; echo \"hello $name\" >&2; read ok; echo 'v=1.2'",
+        );
+
+        let prompt = process
+            .expect(&Regex::new(r"\?$").unwrap(), TIMEOUT)
+            .unwrap();
+        assert_eq!(prompt.line, "name?");
+        assert!(matches!(&prompt.before[..], [Output::Out(l)] if l == "banner"));
+
+        process.write_line("bob").unwrap();
+        let greeting = process
+            .expect(&Regex::new("hello (\\w+)").unwrap(), TIMEOUT)
+            .unwrap();
+        assert!(greeting.stderr);
+        assert_eq!(greeting.get(1), Some("bob"));
+
+        process.write_line("ok").unwrap();
+        let version = Regex::new(r"v=(?P<major>\d+)\.(?P<minor>\d+)").unwrap();
+        let (index, found) = process
+            .expect_any(&[Regex::new("error").unwrap(), version], TIMEOUT)
+            .unwrap();
+        assert_eq!(index, 1);
+        assert_eq!(found.name("minor"), Some("2"));
+
+        assert_eq!(process.expect_exit(TIMEOUT).unwrap().code, Some(0));
+    }
+
+    #[test]
+    fn expect_fails_on_exit_and_timeout() {
+        let process = interactive("read never");
+        let err = process
+            .expect(&Regex::new("x").unwrap(), Duration::from_millis(50))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        process.close_stdin();
+        let err = process
+            .expect(&Regex::new("x").unwrap(), TIMEOUT)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(process.expect_exit(TIMEOUT).unwrap().code, Some(1));
+    }
+}
@@ -0,0 +1,135 @@
+//! Writable stdin for [`super::Process`]
+use super::{Output, OutputSender};
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Cloneable handle to a child's stdin, usable from any thread while output is streamed.
+#[derive(Clone, Default)]
+pub struct StdinHandle {
+    inner: Arc<Mutex<Option<Box<dyn Write + Send>>>>,
+    /// Byte written on close instead of hanging up, for terminals
+    eof: Option<u8>,
+}
+
+impl StdinHandle {
+    pub(crate) fn new<W: Write + Send + 'static>(stdin: Option<W>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(
+                stdin.map(|stdin| Box::new(stdin) as Box<dyn Write + Send>),
+            )),
+            eof: None,
+        }
+    }
+
+    /// Stdin backed by a pseudo-terminal master, closed by sending the terminal's EOF character
+    pub(crate) fn pty(master: File) -> Self {
+        Self {
+            eof: Some(0x04),
+            ..Self::new(Some(master))
+        }
+    }
+
+    /// Write `line` followed by a newline
+    pub fn write_line(&self, line: &str) -> io::Result<()> {
+        self.with(|stdin| {
+            stdin.write_all(line.as_bytes())?;
+            stdin.write_all(b"\n")
+        })
+    }
+
+    /// Write raw bytes
+    pub fn write_all(&self, buf: &[u8]) -> io::Result<()> {
+        self.with(|stdin| stdin.write_all(buf))
+    }
+
+    /// Close stdin so the child reads EOF. Closing twice is a no-op.
+    ///
+    /// On a pseudo-terminal this sends `^D`, which the child only reads as EOF at line start.
+    pub fn close(&self) {
+        let stdin = self.inner.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let (Some(mut stdin), Some(eof)) = (stdin, self.eof) {
+            stdin.write_all(&[eof]).ok();
+        }
+    }
+
+    fn with(&self, f: impl FnOnce(&mut dyn Write) -> io::Result<()>) -> io::Result<()> {
+        let mut stdin = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        match stdin.as_mut() {
+            Some(stdin) => f(stdin.as_mut()).and_then(|_| stdin.flush()),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "process stdin is closed or was not piped",
+            )),
+        }
+    }
+}
+
+/// Input queued to the child's stdin by a background thread right after spawn
+#[derive(Debug, Clone, Default)]
+pub struct InputScript {
+    steps: Vec<Step>,
+    close: bool,
+}
+
+#[derive(Debug, Clone)]
+enum Step {
+    Bytes(Vec<u8>),
+    Delay(Duration),
+}
+
+impl InputScript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue `line` followed by a newline
+    pub fn line(mut self, line: impl Into<String>) -> Self {
+        let mut line = line.into().into_bytes();
+        line.push(b'\n');
+        self.steps.push(Step::Bytes(line));
+        self
+    }
+
+    /// Queue raw bytes
+    pub fn bytes(mut self, bytes: impl Into<Vec<u8>>) -> Self {
+        self.steps.push(Step::Bytes(bytes.into()));
+        self
+    }
+
+    /// Pause before writing the next step
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.steps.push(Step::Delay(delay));
+        self
+    }
+
+    /// Close stdin once every step was written
+    pub fn close(mut self) -> Self {
+        self.close = true;
+        self
+    }
+
+    /// Feed the script on a detached thread, reporting write failures as [`Output::Err`].
+    pub(super) fn spawn(self, stdin: StdinHandle, tx: OutputSender) {
+        thread::spawn(move || {
+            for step in self.steps {
+                let result = match step {
+                    Step::Bytes(bytes) => stdin.write_all(&bytes),
+                    Step::Delay(delay) => {
+                        thread::sleep(delay);
+                        Ok(())
+                    }
+                };
+                if let Err(e) = result {
+                    tx.send(Output::Err(format!("stdin: {e}"))).ok();
+                    return;
+                }
+            }
+            if self.close {
+                stdin.close();
+            }
+        });
+    }
+}
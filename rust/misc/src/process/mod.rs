@@ -0,0 +1,1589 @@
+//! Helper type for processing process output and exit status in non-blocking way
+mod async_process;
+mod builder;
+mod capture;
+mod event;
+mod exit;
+mod expect;
+mod input;
+mod parse;
+mod pipeline;
+mod pool;
+mod progress;
+mod pty;
+mod record;
+mod source;
+mod supervisor;
+mod sys;
+mod tee;
+mod timeout;
+
+pub use async_process::{AsyncOutputStream, AsyncProcess};
+pub use builder::{Limit, ProcessBuilder};
+pub use capture::Capture;
+pub use event::Event;
+pub use exit::{signal_name, Exit, ExitReason, ResourceUsage};
+pub use expect::Match;
+pub use input::{InputScript, StdinHandle};
+pub use parse::{
+    JsonParser, Level, LineParser, LogEntry, LogfmtParser, Parsed, Parsers, PrefixParser,
+};
+pub use pipeline::Pipeline;
+pub use pool::{JobStatus, JobSummary, PoolOptions, PoolSummary, ProcessPool};
+pub use progress::{Progress, ProgressOptions};
+pub use pty::{strip_ansi, PtyLines, PtyOptions};
+pub use record::{JsonLinesDecoder, JsonLinesEncoder, Record, RecordKind, Stream};
+pub use source::{FakeProcess, FakeScript, OutputSource};
+pub use supervisor::{Backoff, Lifecycle, RestartPolicy, Supervisor, SupervisorOptions};
+pub use tee::LogOptions;
+
+use capture::CaptureBuffer;
+use crossbeam_channel::{Receiver, RecvTimeoutError, SendError, TryRecvError};
+use event::{Cursor, OutputSender, Reorder};
+use std::fs::File;
+use std::io::{self, prelude::*, BufReader};
+use std::os::fd::OwnedFd;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tee::Tee;
+use timeout::Activity;
+
+type ProcessHandle = JoinHandle<Result<(), SendError<()>>>;
+type OutputReceiver = Receiver<Event>;
+
+struct ProcessHandlers {
+    /// Taken once the child was reaped by [`Process::kill`] or [`Process::terminate`], after
+    /// which dropping the [`Process`] has nothing left to clean up
+    status: Option<ProcessHandle>,
+}
+
+/// Child and exit state shared between [`Process`] and its helper threads
+struct Shared {
+    child: Mutex<Child>,
+    /// Whether the child leads its own process group, so signals go to the whole group
+    group: bool,
+    started: Instant,
+    state: ExitState,
+}
+
+impl Shared {
+    /// Send `signal` to the child, or to its whole process group when it leads one
+    fn signal(&self, signal: libc::c_int) -> io::Result<()> {
+        let child = self.child.lock().unwrap_or_else(|e| e.into_inner());
+        self.signal_locked(&child, signal)
+    }
+
+    /// [`Shared::signal`] with the `child` lock already held
+    fn signal_locked(&self, child: &Child, signal: libc::c_int) -> io::Result<()> {
+        if self.group {
+            // The group id is the leader's pid, which stays allocated while any member is
+            // alive and may be recycled once the group is empty. After the leader was reaped,
+            // probe for a remaining member before reaching orphaned grandchildren, so an empty
+            // group is never signalled. A pid recycled as a new group leader between probe and
+            // signal is not detected.
+            let probe = match self.state.is_reaped() {
+                true => sys::signal_group(child.id(), 0),
+                false => Ok(()),
+            };
+            return match probe.and_then(|_| sys::signal_group(child.id(), signal)) {
+                Err(e) if e.raw_os_error() == Some(libc::ESRCH) => Ok(()),
+                result => result,
+            };
+        }
+        // The status thread reaps and records the status under this lock, so a child
+        // without a recorded status still owns its pid.
+        if self.state.is_reaped() {
+            return Ok(());
+        }
+        sys::signal(child.id(), signal)
+    }
+}
+
+/// Exit status shared between the status thread and [`Process::wait`] callers
+#[derive(Default)]
+struct ExitState {
+    status: Mutex<Option<Result<Exit, (io::ErrorKind, String)>>>,
+    reason: Mutex<ExitReason>,
+    reaped: Condvar,
+}
+
+impl ExitState {
+    fn set(&self, status: &io::Result<Exit>) {
+        let mut current = self.status.lock().unwrap_or_else(|e| e.into_inner());
+        *current = Some(match status {
+            Ok(status) => Ok(*status),
+            Err(e) => Err((e.kind(), e.to_string())),
+        });
+        self.reaped.notify_all();
+    }
+
+    fn set_reason(&self, reason: ExitReason) {
+        *self.reason.lock().unwrap_or_else(|e| e.into_inner()) = reason;
+    }
+
+    fn reason(&self) -> ExitReason {
+        *self.reason.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn is_reaped(&self) -> bool {
+        self.status
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .is_some()
+    }
+
+    /// Wait up to `timeout` for the child to be reaped, returning whether it was.
+    fn wait_timeout(&self, timeout: Duration) -> bool {
+        let status = self.status.lock().unwrap_or_else(|e| e.into_inner());
+        let (status, _) = self
+            .reaped
+            .wait_timeout_while(status, timeout, |status| status.is_none())
+            .unwrap_or_else(|e| e.into_inner());
+        status.is_some()
+    }
+
+    fn wait(&self) -> io::Result<Exit> {
+        let mut status = self.status.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if let Some(status) = status.as_ref() {
+                return status
+                    .clone()
+                    .map_err(|(kind, msg)| io::Error::new(kind, msg));
+            }
+            status = self.reaped.wait(status).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+/// Options controlling how [`Process`] spawns and drives the child
+#[derive(Debug)]
+pub struct ProcessOptions {
+    /// Where stdin is connected, [`StdioPolicy::Piped`] keeps it writable through [`Process`]
+    pub stdin: StdioPolicy,
+    /// Where stdout is connected, only [`StdioPolicy::Piped`] is streamed as [`Output`]
+    pub stdout: StdioPolicy,
+    /// Where stderr is connected, only [`StdioPolicy::Piped`] is streamed as [`Output`]
+    pub stderr: StdioPolicy,
+    /// Input written to stdin right after spawn, implies piped stdin
+    pub input: Option<InputScript>,
+    /// Kill the child once it has been running for this long
+    pub deadline: Option<Duration>,
+    /// Kill the child once it has produced no stdout/stderr line for this long
+    pub idle_timeout: Option<Duration>,
+    /// Spawn the child in its own process group or session so signals reach its descendants.
+    ///
+    /// Set up through the spawned [`Command`], which keeps it: spawning the same `Command`
+    /// again, even with [`ProcessGroup::Inherit`], still starts a new group or session.
+    pub group: ProcessGroup,
+    /// Attach the child to a pseudo-terminal instead of pipes. The child leads a new session
+    /// and stdin is always writable.
+    ///
+    /// Set up through the spawned [`Command`]: its stdio is reset to `/dev/null` afterwards,
+    /// and it keeps the hook taking the terminal, which spawning it again tolerates.
+    pub pty: Option<PtyOptions>,
+    /// How stdout and stderr, piped or on the terminal, are split into [`Output`] items
+    pub read_mode: ReadMode,
+    /// How many unread [`Output`] items are held and what happens once that many are
+    pub buffer: Buffer,
+    /// Keep the last output of each stream for [`Process::tail`], even when nobody reads it
+    pub capture: Option<Capture>,
+    /// Mirror stdout and stderr to log files while still streaming them
+    pub log: Option<LogOptions>,
+    /// What happens to a child still running when its [`Process`] is dropped
+    pub on_drop: DropPolicy,
+    /// Split lines on `\r` too and report [`Output::Progress`] found in them. Requires
+    /// [`ReadMode::Lines`], spawning fails with [`io::ErrorKind::InvalidInput`] otherwise.
+    pub progress: Option<ProgressOptions>,
+}
+
+impl Default for ProcessOptions {
+    fn default() -> Self {
+        Self {
+            stdin: StdioPolicy::Null,
+            stdout: StdioPolicy::Piped,
+            stderr: StdioPolicy::Piped,
+            input: None,
+            deadline: None,
+            idle_timeout: None,
+            group: ProcessGroup::default(),
+            pty: None,
+            read_mode: ReadMode::default(),
+            buffer: Buffer::default(),
+            capture: None,
+            log: None,
+            on_drop: DropPolicy::default(),
+            progress: None,
+        }
+    }
+}
+
+/// Where one of the child's stdio streams is connected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StdioPolicy {
+    /// A pipe owned by [`Process`]
+    Piped,
+    /// The parent's own stream
+    Inherit,
+    /// `/dev/null`
+    Null,
+}
+
+impl StdioPolicy {
+    fn stdio(self) -> Stdio {
+        match self {
+            StdioPolicy::Piped => Stdio::piped(),
+            StdioPolicy::Inherit => Stdio::inherit(),
+            StdioPolicy::Null => Stdio::null(),
+        }
+    }
+}
+
+/// How stdout and stderr, piped or on the terminal, are turned into [`Output`] items
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReadMode {
+    /// [`Output::Out`]/[`Output::Err`] lines without the trailing `\n` or `\r\n`. Invalid
+    /// UTF-8 is replaced with `U+FFFD` instead of dropping the line.
+    #[default]
+    Lines,
+    /// [`Output::OutBytes`]/[`Output::ErrBytes`] lines without the trailing `\n`, bytes kept
+    /// exactly as written
+    Bytes,
+    /// [`Output::OutBytes`]/[`Output::ErrBytes`] chunks of at most this many bytes, delivered
+    /// as soon as they are read, for output that is not line oriented
+    Chunks(usize),
+}
+
+/// Capacity of the channel between the reader threads and [`OutputStream`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Buffer {
+    /// Hold every unread item, growing without limit
+    #[default]
+    Unbounded,
+    /// Hold at most `capacity` unread items
+    Bounded { capacity: usize, overflow: Overflow },
+}
+
+/// What a full [`Buffer::Bounded`] does with new output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Stop reading until there is room, so the child blocks once its pipe fills up
+    Block,
+    /// Discard the oldest unread items to make room
+    DropOldest,
+    /// Discard new items until there is room
+    DropNewest,
+}
+
+/// What dropping a [`Process`] does with a child that was not killed or waited for.
+///
+/// The child is always reaped once it exits, so none of these leave zombies behind. Reader
+/// threads keep draining the child's pipes until they close, discarding output nobody
+/// receives anymore.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DropPolicy {
+    /// Kill the child (or its whole group) with SIGKILL and block until it was reaped
+    #[default]
+    Kill,
+    /// Leave the child running, a background thread reaps it whenever it exits. Its output is
+    /// still read and then discarded, so the child never blocks on or dies from its pipes.
+    Detach,
+    /// Block until the child exits on its own, discarding any output it still produces
+    Wait,
+}
+
+/// Process group placement of the spawned child
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProcessGroup {
+    /// Stay in the parent's process group, signals only reach the direct child
+    #[default]
+    Inherit,
+    /// Lead a new process group
+    Group,
+    /// Lead a new session, detaching from the controlling terminal
+    Session,
+}
+
+/// Process struct for handleing a [`Child`] in non-blocking way
+pub struct Process {
+    shared: Arc<Shared>,
+    stdin: StdinHandle,
+    pty: Option<File>,
+    rx: OutputReceiver,
+    /// Whether `rx` is already in sequence order, see [`OutputStream::ordered`]
+    sequential: bool,
+    /// Shared by its streams, see [`OutputStream::ordered`]
+    cursor: Cursor,
+    capture: Option<Arc<CaptureBuffer>>,
+    on_drop: DropPolicy,
+    handlers: ProcessHandlers,
+}
+
+impl Process {
+    /// Create new process from [`Child`]
+    pub fn new(command: &mut Command) -> io::Result<Process> {
+        Self::with_options(command, ProcessOptions::default())
+    }
+
+    /// Create new process from [`Child`] configured with [`ProcessOptions`]
+    pub fn with_options(command: &mut Command, options: ProcessOptions) -> io::Result<Process> {
+        Self::spawn(command, options, None, None)
+    }
+
+    /// Spawn with stdin and stdout connected to `stdin` and `stdout` when given instead of
+    /// following `options`, for [`Pipeline`] stages
+    fn spawn(
+        command: &mut Command,
+        options: ProcessOptions,
+        stdin: Option<Stdio>,
+        stdout: Option<Stdio>,
+    ) -> io::Result<Process> {
+        if options.progress.is_some() && options.read_mode != ReadMode::Lines {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "progress requires ReadMode::Lines",
+            ));
+        }
+        let capture = options
+            .capture
+            .map(|limit| Arc::new(CaptureBuffer::new(limit, options.read_mode)));
+        let tee = options
+            .log
+            .map(|log| Tee::open(log, options.read_mode).map(Arc::new))
+            .transpose()?;
+        let (tx, rx) = OutputSender::channel(options.buffer);
+        let tx = tx.capture(capture.clone()).tee(tee);
+        let sequential = tx.sequential();
+        let stdin_policy = match options.input {
+            Some(_) => StdioPolicy::Piped,
+            None => options.stdin,
+        };
+        let pty = options
+            .pty
+            .map(|pty| sys::openpty(pty.rows, pty.cols).map(|fds| (pty.lines, fds)))
+            .transpose()?;
+
+        if let Some((_, (_, slave))) = &pty {
+            command.stdin(Stdio::from(slave.try_clone()?));
+            command.stdout(Stdio::from(slave.try_clone()?));
+            command.stderr(Stdio::from(slave.try_clone()?));
+            unsafe {
+                command.pre_exec(sys::set_controlling_terminal);
+            }
+        } else {
+            command.stdin(stdin.unwrap_or_else(|| stdin_policy.stdio()));
+            command.stdout(stdout.unwrap_or_else(|| options.stdout.stdio()));
+            command.stderr(options.stderr.stdio());
+            match options.group {
+                ProcessGroup::Inherit => {}
+                ProcessGroup::Group => {
+                    command.process_group(0);
+                }
+                ProcessGroup::Session => unsafe {
+                    command.pre_exec(sys::setsid);
+                },
+            }
+        }
+
+        let spawned = command.spawn();
+        if pty.is_some() {
+            // Command keeps its stdio, so release our copies of the terminal's child side.
+            // Otherwise the master never sees the hang-up once the child exits.
+            command.stdin(Stdio::null());
+            command.stdout(Stdio::null());
+            command.stderr(Stdio::null());
+        }
+        let mut process = spawned?;
+
+        let activity = Arc::new(Activity::new());
+        let spawn = |is_stdout, out: Box<dyn Read + Send>| match &options.progress {
+            Some(progress) if options.read_mode == ReadMode::Lines => {
+                let (progress, tx) = (progress.clone(), tx.clone());
+                progress::spawn_reader(is_stdout, out, progress, tx, activity.clone())
+            }
+            _ => {
+                let (mode, tx) = (options.read_mode, tx.clone());
+                spawn_reader(is_stdout, out, mode, tx, activity.clone())
+            }
+        };
+        let (stdin, master, slave, readers) = match pty {
+            Some((lines, (master, slave))) => {
+                let master = File::from(master);
+                let stdin = StdinHandle::pty(master.try_clone()?);
+                let terminal = pty::TerminalReader::new(master.try_clone()?, lines);
+                let reader = spawn(true, Box::new(terminal));
+                (stdin, Some(master), Some(slave), vec![reader])
+            }
+            None => {
+                let mut readers = Vec::new();
+                if let Some(stdout) = process.stdout.take() {
+                    readers.push(spawn(true, Box::new(stdout)));
+                }
+                if let Some(stderr) = process.stderr.take() {
+                    readers.push(spawn(false, Box::new(stderr)));
+                }
+                (StdinHandle::new(process.stdin.take()), None, None, readers)
+            }
+        };
+        if let Some(input) = options.input {
+            input.spawn(stdin.clone(), tx.clone());
+        }
+        let shared = Arc::new(Shared {
+            child: Mutex::new(process),
+            group: master.is_some() || options.group != ProcessGroup::Inherit,
+            started: Instant::now(),
+            state: ExitState::default(),
+        });
+
+        if options.deadline.is_some() || options.idle_timeout.is_some() {
+            timeout::spawn_watchdog(
+                shared.clone(),
+                activity,
+                options.deadline,
+                options.idle_timeout,
+            );
+        }
+
+        let handlers = ProcessHandlers {
+            status: Some(spawn_status_thread(shared.clone(), slave, readers, tx)),
+        };
+
+        Ok(Process {
+            shared,
+            stdin,
+            pty: master,
+            rx,
+            sequential,
+            cursor: Cursor::default(),
+            capture,
+            on_drop: options.on_drop,
+            handlers,
+        })
+    }
+
+    /// OS-assigned process identifier
+    pub fn id(&self) -> u32 {
+        self.shared
+            .child
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .id()
+    }
+
+    /// Get iteratorable stream of outputs
+    pub fn stream(&self) -> OutputStream<'_> {
+        OutputStream {
+            rx: &self.rx,
+            reorder: None,
+            sequential: self.sequential,
+            cursor: Some(&self.cursor),
+            exit: false,
+        }
+    }
+
+    /// Last `n` captured stdout and stderr lines in the order they were read.
+    ///
+    /// Empty unless [`ProcessOptions::capture`] is set. Capture happens as output is read,
+    /// so it is complete once the stream yielded [`Output::Exit`].
+    pub fn tail(&self, n: usize) -> Vec<Output> {
+        self.capture.as_ref().map_or_else(Vec::new, |c| c.tail(n))
+    }
+
+    /// Captured stdout, see [`Process::tail`]
+    pub fn stdout_text(&self) -> String {
+        self.capture
+            .as_ref()
+            .map_or_else(String::new, |c| c.stdout_text())
+    }
+
+    /// Captured stderr, see [`Process::tail`]
+    pub fn stderr_text(&self) -> String {
+        self.capture
+            .as_ref()
+            .map_or_else(String::new, |c| c.stderr_text())
+    }
+
+    /// Get a cloneable handle to stdin, e.g. to feed input from another thread
+    pub fn stdin(&self) -> StdinHandle {
+        self.stdin.clone()
+    }
+
+    /// Write `line` followed by a newline to stdin
+    pub fn write_line(&self, line: &str) -> io::Result<()> {
+        self.stdin.write_line(line)
+    }
+
+    /// Write raw bytes to stdin
+    pub fn write_all(&self, buf: &[u8]) -> io::Result<()> {
+        self.stdin.write_all(buf)
+    }
+
+    /// Close stdin so the child reads EOF
+    pub fn close_stdin(&self) {
+        self.stdin.close()
+    }
+
+    /// Resize the pseudo-terminal, notifying the child with SIGWINCH
+    pub fn resize(&self, rows: u16, cols: u16) -> io::Result<()> {
+        match &self.pty {
+            Some(master) => sys::set_winsize(master, rows, cols),
+            None => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "process is not attached to a pseudo-terminal",
+            )),
+        }
+    }
+
+    /// Block current thread until the process exist, returning its [`Exit`] and
+    /// [`ResourceUsage`].
+    pub fn wait(&self) -> io::Result<Exit> {
+        self.shared.state.wait()
+    }
+
+    /// Kill running process (or its whole group) with SIGKILL and wait for it to be reaped
+    pub fn kill(self) -> io::Result<Exit> {
+        self.shared.signal(libc::SIGKILL)?;
+        self.reaped()
+    }
+
+    /// Send SIGTERM and escalate to SIGKILL if the child is still running after `grace`
+    pub fn terminate(self, grace: Duration) -> io::Result<Exit> {
+        self.terminate_with(libc::SIGTERM, grace)
+    }
+
+    /// Send `signal` and escalate to SIGKILL if the child is still running after `grace`.
+    ///
+    /// With a [`ProcessGroup`] the escalation always goes to the group, so descendants that
+    /// outlive the leader are cleaned up too.
+    pub fn terminate_with(self, signal: libc::c_int, grace: Duration) -> io::Result<Exit> {
+        self.shared.signal(signal)?;
+        if !self.shared.state.wait_timeout(grace) || self.shared.group {
+            self.shared.signal(libc::SIGKILL)?;
+        }
+        self.reaped()
+    }
+
+    /// Wait for the child to be reaped and return the final [`Exit`].
+    ///
+    /// The status thread is left to finish in the background, as it also waits for the
+    /// readers, which block as long as any descendant holds stdout or stderr open.
+    fn reaped(mut self) -> io::Result<Exit> {
+        self.handlers.status.take();
+        self.shared.state.wait()
+    }
+}
+
+impl Drop for Process {
+    /// Apply [`ProcessOptions::on_drop`] unless the child was already killed or terminated
+    fn drop(&mut self) {
+        if self.handlers.status.is_none() {
+            return;
+        }
+        match self.on_drop {
+            DropPolicy::Kill => {
+                // The status thread reaps before waiting for the readers, so this returns even
+                // when a descendant still holds the pipes open.
+                if self.shared.signal(libc::SIGKILL).is_ok() {
+                    self.shared.state.wait().ok();
+                }
+            }
+            DropPolicy::Detach => {}
+            DropPolicy::Wait => {
+                // Keep draining so readers blocked on a full `Buffer::Bounded` let the child
+                // make progress.
+                while !self.shared.state.is_reaped() {
+                    let received = self.rx.recv_timeout(Duration::from_millis(50));
+                    if let Err(RecvTimeoutError::Disconnected) = received {
+                        self.shared.state.wait().ok();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// OutputStream iterator
+pub struct OutputStream<'a> {
+    rx: &'a OutputReceiver,
+    reorder: Option<Reorder>,
+    /// Events arrive in sequence order already, with gaps where events were dropped
+    sequential: bool,
+    /// Where earlier streams of the same source left off, `None` for sequential sources
+    cursor: Option<&'a Cursor>,
+    exit: bool,
+}
+
+/// How long [`OutputStream::recv`] may block
+enum Wait {
+    Block,
+    Poll,
+    Until(Instant),
+}
+
+impl<'a> OutputStream<'a> {
+    /// Yield events strictly in the order they were read across stdout and stderr.
+    ///
+    /// By default events are yielded as they reach the channel, where output read at nearly
+    /// the same time on both streams may swap places.
+    /// Ordering resumes after the last event any earlier stream of the process yielded.
+    /// A lossy [`Buffer`] delivers in order already, so this has no effect there.
+    pub fn ordered(mut self) -> Self {
+        if !self.sequential {
+            let mut reorder = Reorder::new(self.cursor.map_or(0, Cursor::position));
+            for event in self.cursor.map(Cursor::take).unwrap_or_default() {
+                reorder.push(event);
+            }
+            self.reorder = Some(reorder);
+        }
+        self
+    }
+
+    /// No blocking equivalent of next
+    pub fn try_next(&mut self) -> Option<Output> {
+        self.recv(Wait::Poll).ok().flatten().map(|e| e.output)
+    }
+
+    /// Blocking equivalent of next that gives up after `timeout`.
+    ///
+    /// Returns `Ok(None)` once the stream ended and [`io::ErrorKind::TimedOut`] when no output
+    /// arrived in time.
+    pub fn next_timeout(&mut self, timeout: Duration) -> io::Result<Option<Output>> {
+        match self.recv(Wait::Until(Instant::now() + timeout)) {
+            Ok(event) => Ok(event.map(|e| e.output)),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("no output within {timeout:?}"),
+            )),
+        }
+    }
+
+    /// Next output with its timestamp and sequence number
+    pub fn next_event(&mut self) -> Option<Event> {
+        self.recv(Wait::Block).ok().flatten()
+    }
+
+    /// Iterate over outputs with their timestamps and sequence numbers
+    pub fn events(mut self) -> impl Iterator<Item = Event> + 'a {
+        std::iter::from_fn(move || self.next_event())
+    }
+
+    /// Receive the next event, `Err` only when `wait` expired first
+    fn recv(&mut self, wait: Wait) -> Result<Option<Event>, RecvTimeoutError> {
+        if self.exit {
+            return Ok(None);
+        }
+        loop {
+            let event = match self.reorder.as_mut() {
+                Some(reorder) => reorder.pop(),
+                None => self.cursor.and_then(Cursor::pop),
+            };
+            if let Some(event) = event {
+                return Ok(Some(self.yielded(event)));
+            }
+            let received = match wait {
+                Wait::Block => self.rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                Wait::Poll => self.rx.try_recv().map_err(|e| match e {
+                    TryRecvError::Empty => RecvTimeoutError::Timeout,
+                    TryRecvError::Disconnected => RecvTimeoutError::Disconnected,
+                }),
+                Wait::Until(deadline) => self.rx.recv_deadline(deadline),
+            };
+            match (received, self.reorder.as_mut()) {
+                (Ok(event), Some(reorder)) => reorder.push(event),
+                (Ok(event), None) => return Ok(Some(self.yielded(event))),
+                (Err(RecvTimeoutError::Disconnected), reorder) => {
+                    let event = reorder.and_then(Reorder::drain);
+                    return Ok(event.map(|event| self.yielded(event)));
+                }
+                (Err(e), _) => return Err(e),
+            }
+        }
+    }
+
+    fn yielded(&mut self, event: Event) -> Event {
+        self.exit = matches!(event.output, Output::Exit(..));
+        if let Some(cursor) = self.cursor {
+            cursor.yielded(&event);
+        }
+        event
+    }
+}
+
+impl Drop for OutputStream<'_> {
+    /// Hand events held back for ordering to the next stream instead of losing them
+    fn drop(&mut self) {
+        if let (Some(cursor), Some(reorder)) = (self.cursor, self.reorder.take()) {
+            cursor.put_back(reorder.into_pending());
+        }
+    }
+}
+
+impl<'a> Iterator for OutputStream<'a> {
+    type Item = Output;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_event().map(|e| e.output)
+    }
+}
+
+/// Output produced by [`Process`] and [`OutputStream`]
+#[derive(Debug)]
+pub enum Output {
+    /// Source stdout
+    Out(String),
+    /// Source stderr or internal io::Error
+    Err(String),
+    /// Source stdout as raw bytes, see [`ReadMode`]
+    OutBytes(Vec<u8>),
+    /// Source stderr as raw bytes, see [`ReadMode`]
+    ErrBytes(Vec<u8>),
+    /// Exit status
+    Exit(Result<Exit, io::Error>),
+    /// Supervision event emitted by [`Supervisor`]
+    Lifecycle(Lifecycle),
+    /// Number of items discarded by a full [`Buffer`] right before this one
+    Dropped(u64),
+    /// Output of the [`Pipeline`] stage with this index
+    Stage(usize, Box<Output>),
+    /// Progress found in stdout or stderr, see [`ProgressOptions`]
+    Progress(Progress),
+}
+
+impl std::fmt::Display for Output {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Output::Out(msg) => msg.fmt(f),
+            Output::Err(msg) => write!(f, "[Error] {msg}"),
+            Output::OutBytes(bytes) => String::from_utf8_lossy(bytes).fmt(f),
+            Output::ErrBytes(bytes) => write!(f, "[Error] {}", String::from_utf8_lossy(bytes)),
+            Output::Exit(Ok(exit)) => exit.fmt(f),
+            Output::Lifecycle(event) => event.fmt(f),
+            Output::Dropped(count) => write!(f, "[Dropped] {count} outputs"),
+            Output::Stage(index, output) => write!(f, "[Stage {index}] {output}"),
+            Output::Progress(progress) => write!(f, "[Progress] {progress}"),
+            Output::Exit(Err(e)) => write!(f, "[Error] exit status unavailable: {e}"),
+        }
+    }
+}
+
+fn spawn_reader<R: Read + Send + 'static>(
+    is_stdout: bool,
+    out: R,
+    mode: ReadMode,
+    tx: OutputSender,
+    activity: Arc<Activity>,
+) -> ProcessHandle {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(out);
+        let mut buf = match mode {
+            ReadMode::Chunks(size) => vec![0; size.max(1)],
+            ReadMode::Lines | ReadMode::Bytes => Vec::new(),
+        };
+        loop {
+            let read = match mode {
+                ReadMode::Chunks(_) => reader.read(&mut buf),
+                ReadMode::Lines | ReadMode::Bytes => {
+                    buf.clear();
+                    reader.read_until(b'\n', &mut buf)
+                }
+            };
+            let len = match read {
+                Ok(0) => break,
+                Ok(len) => len,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    tx.send(Output::Err(e.to_string())).ok();
+                    break;
+                }
+            };
+            activity.touch();
+
+            let output = match mode {
+                ReadMode::Lines => {
+                    let line = buf.strip_suffix(b"\n").unwrap_or(&buf);
+                    let line = line.strip_suffix(b"\r").unwrap_or(line);
+                    let line = String::from_utf8_lossy(line).into_owned();
+                    if is_stdout {
+                        Output::Out(line)
+                    } else {
+                        Output::Err(line)
+                    }
+                }
+                ReadMode::Bytes | ReadMode::Chunks(_) => {
+                    let bytes = match mode {
+                        ReadMode::Bytes => buf.strip_suffix(b"\n").unwrap_or(&buf).to_vec(),
+                        _ => buf[..len].to_vec(),
+                    };
+                    if is_stdout {
+                        Output::OutBytes(bytes)
+                    } else {
+                        Output::ErrBytes(bytes)
+                    }
+                }
+            };
+
+            // Keep reading once the `Process` is gone, a detached child must not see its pipe
+            // close, see `DropPolicy::Detach`
+            tx.send(output).ok();
+        }
+        Ok(())
+    })
+}
+
+/// Block until the child exits, reap it and report [`Output::Exit`] once both readers drained.
+///
+/// The thread sleeps in `waitid(WNOWAIT)` without holding the [`Child`] lock, so it costs
+/// nothing while the child runs and never competes with [`Process::kill`]. `slave`, our copy
+/// of the terminal's child side, is closed once the child is reaped, so the master only
+/// hangs up after everything the child wrote can be read.
+fn spawn_status_thread(
+    shared: Arc<Shared>,
+    slave: Option<OwnedFd>,
+    readers: Vec<ProcessHandle>,
+    tx: OutputSender,
+) -> ProcessHandle {
+    thread::spawn(move || {
+        let state = &shared.state;
+        let pid = shared.child.lock().unwrap_or_else(|e| e.into_inner()).id();
+        let exited = sys::wait_exited(pid);
+        // Reap through wait4 for the resource usage. The lock still guards the pid, see
+        // `Shared::signal`.
+        let child = shared.child.lock().unwrap_or_else(|e| e.into_inner());
+        let exit = exited
+            .and_then(|_| sys::wait4(pid))
+            .map(|(status, usage)| Exit {
+                usage: Some(ResourceUsage::new(&usage, shared.started.elapsed())),
+                ..Exit::new(status, state.reason())
+            });
+        state.set(&exit);
+        drop(child);
+        drop(slave);
+
+        for reader in readers {
+            reader.join().ok();
+        }
+
+        tx.send(Output::Exit(exit))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streams_output_before_exit() {
+        let process =
+            Process::new(Command::new("sh").args(["-c", "echo out; echo err >&2; exit 3"]))
+                .unwrap();
+
+        let outputs: Vec<Output> = process.stream().collect();
+
+        assert!(outputs
+            .iter()
+            .any(|o| matches!(o, Output::Out(l) if l == "out")));
+        assert!(outputs
+            .iter()
+            .any(|o| matches!(o, Output::Err(l) if l == "err")));
+        assert!(matches!(
+            outputs.last(),
+            Some(Output::Exit(Ok(Exit { code: Some(3), .. })))
+        ));
+    }
+
+    #[test]
+    fn wait_does_not_race_status_thread() {
+        let process = Process::new(Command::new("sh").args(["-c", "sleep 0.1; exit 7"])).unwrap();
+
+        assert_eq!(process.wait().unwrap().code, Some(7));
+        assert_eq!(process.wait().unwrap().code, Some(7));
+    }
+
+    #[test]
+    fn feeds_stdin_while_streaming() {
+        let options = ProcessOptions {
+            stdin: StdioPolicy::Piped,
+            ..Default::default()
+        };
+        let process = Process::with_options(
+            Command::new("sh").args(["-c", "while read l; do echo \"got $l\"; done"]),
+            options,
+        )
+        .unwrap();
+        let mut stream = process.stream();
+
+        process.write_line("one").unwrap();
+        assert!(matches!(stream.next(), Some(Output::Out(l)) if l == "got one"));
+        process.write_all(b"two\n").unwrap();
+        assert!(matches!(stream.next(), Some(Output::Out(l)) if l == "got two"));
+        process.close_stdin();
+        assert!(matches!(
+            stream.next(),
+            Some(Output::Exit(Ok(Exit { code: Some(0), .. })))
+        ));
+        assert!(process.write_line("three").is_err());
+    }
+
+    #[test]
+    fn queued_input_script() {
+        let options = ProcessOptions {
+            input: Some(InputScript::new().line("a").line("b").close()),
+            ..Default::default()
+        };
+        let process = Process::with_options(&mut Command::new("cat"), options).unwrap();
+
+        let lines: Vec<String> = process.stream().map(|o| o.to_string()).collect();
+
+        assert_eq!(lines, ["a", "b", "exited with code 0"]);
+    }
+
+    #[test]
+    fn deadline_kills_child() {
+        let options = ProcessOptions {
+            deadline: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        let process = Process::with_options(Command::new("sleep").arg("30"), options).unwrap();
+
+        let exit = process.stream().last();
+
+        assert!(matches!(
+            exit,
+            Some(Output::Exit(Ok(Exit {
+                code: None,
+                reason: ExitReason::Deadline(_),
+                ..
+            })))
+        ));
+    }
+
+    #[test]
+    fn idle_timeout_resets_on_output() {
+        let options = ProcessOptions {
+            idle_timeout: Some(Duration::from_millis(300)),
+            ..Default::default()
+        };
+        let script = "for i in 1 2 3; do echo $i; sleep 0.1; done; exec sleep 30";
+        let process =
+            Process::with_options(Command::new("sh").args(["-c", script]), options).unwrap();
+
+        let outputs: Vec<Output> = process.stream().collect();
+
+        assert_eq!(outputs.len(), 4);
+        assert!(matches!(
+            outputs.last(),
+            Some(Output::Exit(Ok(Exit {
+                reason: ExitReason::Idle(_),
+                ..
+            })))
+        ));
+    }
+
+    #[test]
+    fn next_timeout_reports_silence() {
+        let process =
+            Process::new(Command::new("sh").args(["-c", "sleep 0.3; echo late"])).unwrap();
+        let mut stream = process.stream();
+
+        let err = stream.next_timeout(Duration::from_millis(50)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(matches!(
+            stream.next_timeout(Duration::from_secs(5)),
+            Ok(Some(Output::Out(l))) if l == "late"
+        ));
+    }
+
+    #[test]
+    fn has_exited_sees_unreaped_child() {
+        let mut running = Command::new("sleep").arg("30").spawn().unwrap();
+        let mut exited = Command::new("true").spawn().unwrap();
+        sys::wait_exited(exited.id()).unwrap();
+
+        assert!(!sys::has_exited(running.id()).unwrap());
+        assert!(sys::has_exited(exited.id()).unwrap());
+        // Still a zombie, so reaping it works
+        assert!(exited.wait().unwrap().success());
+        running.kill().ok();
+        running.wait().ok();
+    }
+
+    #[test]
+    fn kill_returns_before_descendants_close_pipes() {
+        let process =
+            Process::new(Command::new("sh").args(["-c", "sleep 3 & echo started; wait"])).unwrap();
+        assert!(matches!(process.stream().next(), Some(Output::Out(l)) if l == "started"));
+
+        let started = Instant::now();
+        let exit = process.kill().unwrap();
+
+        assert_eq!(exit.signal, Some(libc::SIGKILL));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn kill_stops_running_process() {
+        let process = Process::new(&mut Command::new("yes")).unwrap();
+        let shared = process.shared.clone();
+
+        let exit = process.kill().unwrap();
+        assert_eq!(exit.code, None);
+        assert_eq!(exit.signal_name(), Some("SIGKILL"));
+        assert!(!shared.state.wait().unwrap().success());
+    }
+
+    #[test]
+    fn terminate_lets_child_exit_gracefully() {
+        let script = "trap 'echo bye; exit 5' TERM; echo ready; while true; do sleep 0.05; done";
+        let process = Process::new(Command::new("sh").args(["-c", script])).unwrap();
+        assert!(matches!(process.stream().next(), Some(Output::Out(l)) if l == "ready"));
+
+        let exit = process.terminate(Duration::from_secs(5)).unwrap();
+
+        assert_eq!(exit.code, Some(5));
+    }
+
+    #[test]
+    fn terminate_escalates_after_grace() {
+        let script = "trap '' TERM; echo ready; while true; do sleep 0.05; done";
+        let process = Process::new(Command::new("sh").args(["-c", script])).unwrap();
+        assert!(matches!(process.stream().next(), Some(Output::Out(l)) if l == "ready"));
+
+        let exit = process.terminate(Duration::from_millis(200)).unwrap();
+
+        assert_eq!(exit.code, None);
+    }
+
+    #[test]
+    fn kill_reaches_process_group() {
+        let options = ProcessOptions {
+            group: ProcessGroup::Group,
+            ..Default::default()
+        };
+        let script = "sleep 30 & echo started; wait";
+        let process =
+            Process::with_options(Command::new("sh").args(["-c", script]), options).unwrap();
+        assert!(matches!(process.stream().next(), Some(Output::Out(l)) if l == "started"));
+
+        let started = std::time::Instant::now();
+        process.kill().unwrap();
+
+        // The backgrounded sleep holds the pipes open, so this only returns once it was killed.
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn terminate_reaches_session() {
+        let options = ProcessOptions {
+            group: ProcessGroup::Session,
+            ..Default::default()
+        };
+        let script = "sh -c 'trap \"\" TERM; sleep 30' & echo started; wait";
+        let process =
+            Process::with_options(Command::new("sh").args(["-c", script]), options).unwrap();
+        assert!(matches!(process.stream().next(), Some(Output::Out(l)) if l == "started"));
+
+        let started = std::time::Instant::now();
+        process.terminate(Duration::from_millis(100)).unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn session_command_can_be_spawned_again() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "ps -o sid= -p $$ | tr -d ' '"]);
+
+        for _ in 0..2 {
+            let options = ProcessOptions {
+                group: ProcessGroup::Session,
+                ..Default::default()
+            };
+            let process = Process::with_options(&mut command, options).unwrap();
+            let pid = process.id();
+
+            let outputs: Vec<String> = process.stream().map(|o| o.to_string()).collect();
+
+            assert_eq!(outputs, [pid.to_string(), "exited with code 0".into()]);
+        }
+    }
+
+    #[test]
+    fn pty_mode_reports_terminal() {
+        let options = ProcessOptions {
+            pty: Some(PtyOptions {
+                rows: 40,
+                cols: 100,
+                lines: PtyLines::StripAnsi,
+            }),
+            ..Default::default()
+        };
+        let script = "test -t 1 && echo tty; stty size; printf '\\033[31mred\\033[0m\\n'";
+        let process =
+            Process::with_options(Command::new("sh").args(["-c", script]), options).unwrap();
+
+        let lines: Vec<String> = process.stream().map(|o| o.to_string()).collect();
+
+        assert_eq!(lines, ["tty", "40 100", "red", "exited with code 0"]);
+    }
+
+    #[test]
+    fn pty_command_can_be_spawned_again() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "test -t 0 && echo tty"]);
+
+        for _ in 0..2 {
+            let options = ProcessOptions {
+                pty: Some(PtyOptions::default()),
+                ..Default::default()
+            };
+            let process = Process::with_options(&mut command, options).unwrap();
+
+            let lines: Vec<String> = process.stream().map(|o| o.to_string()).collect();
+
+            assert_eq!(lines, ["tty", "exited with code 0"]);
+        }
+    }
+
+    #[test]
+    fn pty_mode_accepts_input() {
+        let options = ProcessOptions {
+            pty: Some(PtyOptions::default()),
+            ..Default::default()
+        };
+        let process = Process::with_options(
+            Command::new("sh").args(["-c", "echo ready; read l; echo \"got $l\""]),
+            options,
+        )
+        .unwrap();
+        let mut stream = process.stream();
+        assert!(matches!(stream.next(), Some(Output::Out(l)) if l == "ready"));
+
+        process.write_line("hi").unwrap();
+        let lines: Vec<String> = stream.map(|o| o.to_string()).collect();
+
+        // The terminal echoes the input back before the child answers.
+        assert_eq!(lines, ["hi", "got hi", "exited with code 0"]);
+    }
+
+    #[test]
+    fn pty_mode_keeps_raw_bytes() {
+        let options = ProcessOptions {
+            pty: Some(PtyOptions::default()),
+            read_mode: ReadMode::Bytes,
+            ..Default::default()
+        };
+        let script = "printf '\\033[1ma\\377\\rb\\n'";
+        let process =
+            Process::with_options(Command::new("sh").args(["-c", script]), options).unwrap();
+
+        let outputs: Vec<Output> = process.stream().collect();
+
+        // The terminal turns `\n` into `\r\n`, only the `\n` is stripped.
+        assert!(matches!(&outputs[0], Output::OutBytes(b) if b == b"\x1b[1ma\xff\rb\r"));
+        assert!(matches!(outputs[1], Output::Exit(Ok(_))));
+    }
+
+    #[test]
+    fn events_are_stamped_and_ordered() {
+        let script = "for i in 1 2 3 4 5; do echo out$i; echo err$i >&2; done";
+        let process = Process::new(Command::new("sh").args(["-c", script])).unwrap();
+
+        let events: Vec<Event> = process.stream().ordered().events().collect();
+
+        assert_eq!(events.len(), 11);
+        for (i, pair) in events.windows(2).enumerate() {
+            assert_eq!(pair[0].seq, i as u64);
+            assert_eq!(pair[1].seq, i as u64 + 1);
+            assert!(pair[0].at <= pair[1].at);
+            assert!(pair[0].elapsed <= pair[1].elapsed);
+        }
+        assert!(matches!(events.last().unwrap().output, Output::Exit(_)));
+    }
+
+    #[test]
+    fn ordered_stream_hands_back_held_events() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let started = Instant::now();
+        for (seq, line) in [(1, "second"), (0, "first"), (2, "third")] {
+            let output = Output::Out(line.into());
+            tx.send(Event {
+                seq,
+                at: started,
+                elapsed: Duration::ZERO,
+                output,
+            })
+            .unwrap();
+        }
+        let cursor = Cursor::default();
+        let stream = || OutputStream {
+            rx: &rx,
+            reorder: None,
+            sequential: false,
+            cursor: Some(&cursor),
+            exit: false,
+        };
+
+        // Holds back "second" until "first" arrived, then stops before yielding it
+        let mut ordered = stream().ordered();
+        assert!(matches!(ordered.next(), Some(Output::Out(l)) if l == "first"));
+        drop(ordered);
+
+        let rest: Vec<String> = stream().take(2).map(|o| o.to_string()).collect();
+        assert_eq!(rest, ["second", "third"]);
+    }
+
+    #[test]
+    fn ordering_resumes_after_consumed_events() {
+        let script = "echo a; sleep 0.2; echo b >&2; sleep 2";
+        let process = Process::new(Command::new("sh").args(["-c", script])).unwrap();
+        assert!(matches!(process.stream().next(), Some(Output::Out(l)) if l == "a"));
+
+        let next = process
+            .stream()
+            .ordered()
+            .next_timeout(Duration::from_secs(1))
+            .unwrap();
+
+        assert!(matches!(next, Some(Output::Err(l)) if l == "b"));
+    }
+
+    fn read_with(mode: ReadMode, script: &str) -> Vec<Output> {
+        let options = ProcessOptions {
+            read_mode: mode,
+            ..Default::default()
+        };
+        let process =
+            Process::with_options(Command::new("sh").args(["-c", script]), options).unwrap();
+        process.stream().collect()
+    }
+
+    #[test]
+    fn lines_decode_invalid_utf8_lossily() {
+        let outputs = read_with(ReadMode::Lines, "printf 'a\\377b\\r\\nok\\n'");
+
+        assert!(matches!(&outputs[0], Output::Out(l) if l == "a\u{FFFD}b"));
+        assert!(matches!(&outputs[1], Output::Out(l) if l == "ok"));
+    }
+
+    #[test]
+    fn bytes_mode_preserves_raw_lines() {
+        let outputs = read_with(ReadMode::Bytes, "printf 'a\\377b\\r\\n' >&2");
+
+        assert!(matches!(&outputs[0], Output::ErrBytes(b) if b == b"a\xffb\r"));
+    }
+
+    #[test]
+    fn chunk_mode_splits_unterminated_output() {
+        let outputs = read_with(
+            ReadMode::Chunks(4),
+            "printf '\\000\\001\\002\\003\\004\\005'",
+        );
+
+        let bytes: Vec<u8> = outputs
+            .iter()
+            .filter_map(|o| match o {
+                Output::OutBytes(chunk) => {
+                    assert!(chunk.len() <= 4);
+                    Some(chunk.clone())
+                }
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        assert_eq!(bytes, [0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn exit_reports_resource_usage() {
+        let script = "i=0; while [ $i -lt 20000 ]; do i=$((i+1)); done; sleep 0.1";
+        let process = Process::new(Command::new("sh").args(["-c", script])).unwrap();
+
+        let usage = process.wait().unwrap().usage.unwrap();
+        let Some(Output::Exit(Ok(exit))) = process.stream().last() else {
+            panic!("missing exit event");
+        };
+
+        assert_eq!(exit.usage, Some(usage));
+        assert!(usage.wall_time >= Duration::from_millis(100));
+        assert!(usage.user_time + usage.system_time > Duration::ZERO);
+        assert!(usage.max_rss > 0);
+        assert!(usage.voluntary_context_switches > 0);
+    }
+
+    fn buffered(capacity: usize, overflow: Overflow, script: &str) -> Process {
+        let options = ProcessOptions {
+            buffer: Buffer::Bounded { capacity, overflow },
+            ..Default::default()
+        };
+        Process::with_options(Command::new("sh").args(["-c", script]), options).unwrap()
+    }
+
+    /// Lines kept and the total reported as dropped
+    fn kept_and_dropped(outputs: &[Output]) -> (Vec<&str>, u64) {
+        let kept = outputs
+            .iter()
+            .filter_map(|o| match o {
+                Output::Out(l) => Some(l.as_str()),
+                _ => None,
+            })
+            .collect();
+        let dropped = outputs
+            .iter()
+            .map(|o| match o {
+                Output::Dropped(count) => *count,
+                _ => 0,
+            })
+            .sum();
+        (kept, dropped)
+    }
+
+    #[test]
+    fn drop_newest_keeps_first_lines() {
+        let process = buffered(8, Overflow::DropNewest, "seq 1 1000");
+        process.wait().unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        let outputs: Vec<Output> = process.stream().collect();
+        let (kept, dropped) = kept_and_dropped(&outputs);
+
+        assert_eq!(kept[..3], ["1", "2", "3"]);
+        assert!(dropped > 0);
+        assert_eq!(kept.len() as u64 + dropped, 1000);
+        assert!(matches!(outputs.last(), Some(Output::Exit(Ok(_)))));
+    }
+
+    #[test]
+    fn drop_oldest_keeps_latest_lines_in_order() {
+        let process = buffered(8, Overflow::DropOldest, "seq 1 1000");
+        process.wait().unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        let events: Vec<Event> = process.stream().ordered().events().collect();
+        let seqs: Vec<u64> = events.iter().map(|e| e.seq).collect();
+        let outputs: Vec<Output> = events.into_iter().map(|e| e.output).collect();
+        let (kept, dropped) = kept_and_dropped(&outputs);
+
+        assert!(seqs.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(kept.last(), Some(&"1000"));
+        assert!(outputs.len() <= 8);
+        assert_eq!(kept.len() as u64 + dropped, 1000);
+        assert!(matches!(outputs.last(), Some(Output::Exit(Ok(_)))));
+    }
+
+    #[test]
+    fn blocking_buffer_applies_backpressure() {
+        let process = buffered(1, Overflow::Block, "seq 1 20000");
+
+        assert!(!process
+            .shared
+            .state
+            .wait_timeout(Duration::from_millis(200)));
+
+        let outputs: Vec<Output> = process.stream().collect();
+        let (kept, dropped) = kept_and_dropped(&outputs);
+        assert_eq!((kept.len(), dropped), (20000, 0));
+    }
+
+    #[test]
+    fn captures_tail_without_reading_stream() {
+        let options = ProcessOptions {
+            capture: Some(Capture::Lines(2)),
+            buffer: Buffer::Bounded {
+                capacity: 2,
+                overflow: Overflow::DropNewest,
+            },
+            ..Default::default()
+        };
+        let script = "seq 1 100; echo oops >&2; exit 1";
+        let process =
+            Process::with_options(Command::new("sh").args(["-c", script]), options).unwrap();
+
+        // The buffer only kept the first lines, the capture still has the last ones. The exit
+        // follows every line read, so the capture is complete once it arrived.
+        assert!(matches!(process.stream().last(), Some(Output::Exit(_))));
+
+        assert_eq!(process.stdout_text(), "99\n100\n");
+        assert_eq!(process.stderr_text(), "oops\n");
+        // Order across streams depends on reader scheduling, see the `CaptureBuffer` tests
+        let mut tail: Vec<String> = process.tail(3).iter().map(|o| o.to_string()).collect();
+        tail.sort_unstable();
+        assert_eq!(tail, ["100", "99", "[Error] oops"]);
+    }
+
+    #[test]
+    fn tees_output_to_log_file() {
+        let path = std::env::temp_dir().join(format!("misc-tee-{}.log", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let options = ProcessOptions {
+            log: Some(LogOptions::combined(&path).timestamps()),
+            ..Default::default()
+        };
+        let script = "echo out; echo err >&2";
+        let process =
+            Process::with_options(Command::new("sh").args(["-c", script]), options).unwrap();
+
+        assert_eq!(process.stream().count(), 3);
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        let mut lines: Vec<&str> = log.lines().map(|l| l.split_once(' ').unwrap().1).collect();
+        lines.sort_unstable();
+        assert_eq!(lines, ["[Error] err", "out"]);
+        assert!(log
+            .lines()
+            .all(|l| l.split(' ').next().unwrap().ends_with('Z')));
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn displays_exit_errors() {
+        let output = Output::Exit(Err(io::Error::other("wait failed")));
+
+        assert_eq!(
+            output.to_string(),
+            "[Error] exit status unavailable: wait failed"
+        );
+    }
+
+    fn with_drop_policy(script: &str, on_drop: DropPolicy) -> Process {
+        let options = ProcessOptions {
+            on_drop,
+            ..Default::default()
+        };
+        Process::with_options(Command::new("sh").args(["-c", script]), options).unwrap()
+    }
+
+    /// Whether `pid` still exists, zombies included
+    fn exists(pid: u32) -> bool {
+        sys::signal(pid, 0).is_ok()
+    }
+
+    #[test]
+    fn drop_kills_and_reaps_child() {
+        let process = with_drop_policy("exec sleep 30", DropPolicy::Kill);
+        let pid = process.id();
+
+        let started = Instant::now();
+        drop(process);
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(!exists(pid));
+    }
+
+    #[test]
+    fn detached_child_is_reaped_once_it_exits() {
+        let process = with_drop_policy("exec sleep 0.2", DropPolicy::Detach);
+        let pid = process.id();
+
+        drop(process);
+        assert!(exists(pid));
+
+        thread::sleep(Duration::from_millis(600));
+        assert!(!exists(pid));
+    }
+
+    #[test]
+    fn detached_child_keeps_writing_after_drop() {
+        let path = std::env::temp_dir().join(format!("misc-detach-{}", std::process::id()));
+        let script = format!(
+            "sleep 0.2; for i in 1 2 3 4 5; do echo $i; echo $i >&2; sleep .05; done; touch {}",
+            path.display()
+        );
+        let process = with_drop_policy(&script, DropPolicy::Detach);
+
+        drop(process);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !path.exists() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(path.exists());
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn drop_waits_while_draining_output() {
+        let options = ProcessOptions {
+            buffer: Buffer::Bounded {
+                capacity: 1,
+                overflow: Overflow::Block,
+            },
+            on_drop: DropPolicy::Wait,
+            ..Default::default()
+        };
+        let process =
+            Process::with_options(Command::new("seq").args(["1", "20000"]), options).unwrap();
+        let pid = process.id();
+
+        drop(process);
+
+        assert!(!exists(pid));
+    }
+
+    #[test]
+    fn reports_progress_from_redrawn_lines() {
+        let options = ProcessOptions {
+            progress: Some(ProgressOptions::default()),
+            ..Default::default()
+        };
+        let script = r"printf '\r 10%%\r 50%%\r100%%\ncopied 3/4 files\r\nerror\n' >&2";
+        let process =
+            Process::with_options(Command::new("sh").args(["-c", script]), options).unwrap();
+
+        let outputs: Vec<String> = process.stream().map(|o| o.to_string()).collect();
+
+        assert_eq!(
+            outputs[..7],
+            [
+                "[Progress] 10.0%",
+                "[Progress] 50.0%",
+                "[Error] 100%",
+                "[Progress] 100.0%",
+                "[Error] copied 3/4 files",
+                "[Progress] 3/4 (75.0%)",
+                "[Error] error",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_progress_from_terminal() {
+        let options = ProcessOptions {
+            pty: Some(PtyOptions {
+                lines: PtyLines::StripAnsi,
+                ..Default::default()
+            }),
+            progress: Some(ProgressOptions::default()),
+            ..Default::default()
+        };
+        let script = r"printf '\033[32m 50%%\033[0m\r100%%\n' >&2";
+        let process =
+            Process::with_options(Command::new("sh").args(["-c", script]), options).unwrap();
+
+        let outputs: Vec<String> = process.stream().map(|o| o.to_string()).collect();
+
+        assert_eq!(
+            outputs,
+            [
+                "[Progress] 50.0%",
+                "100%",
+                "[Progress] 100.0%",
+                "exited with code 0"
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_progress_without_lines() {
+        let options = ProcessOptions {
+            progress: Some(ProgressOptions::default()),
+            read_mode: ReadMode::Chunks(64),
+            ..Default::default()
+        };
+
+        let result = Process::with_options(&mut Command::new("true"), options);
+
+        assert!(matches!(result, Err(e) if e.kind() == io::ErrorKind::InvalidInput));
+    }
+}
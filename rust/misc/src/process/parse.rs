@@ -0,0 +1,334 @@
+//! Structured parsing of child output lines: JSON logs, logfmt and `[LEVEL]` prefixes
+use super::{Event, Output, OutputStream};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Severity of a parsed line, ordered from least to most severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    /// Parse common spellings case-insensitively, e.g. `warn`, `WARNING`, `err` or `fatal`
+    pub fn parse(name: &str) -> Option<Level> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "trace" | "trc" => Some(Level::Trace),
+            "debug" | "dbg" => Some(Level::Debug),
+            "info" | "inf" | "notice" => Some(Level::Info),
+            "warn" | "wrn" | "warning" => Some(Level::Warn),
+            "error" | "err" | "fatal" | "critical" | "crit" | "panic" => Some(Level::Error),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Level::Trace => "TRACE",
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+        };
+        f.pad(name)
+    }
+}
+
+/// Level, message and remaining fields extracted from one line
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogEntry {
+    pub level: Option<Level>,
+    pub message: String,
+    /// Every other key, non-string JSON values kept as JSON text
+    pub fields: BTreeMap<String, String>,
+}
+
+/// Turns a line of output into a [`LogEntry`], `None` when the line is not in its format
+pub trait LineParser: Send + Sync {
+    fn parse(&self, line: &str) -> Option<LogEntry>;
+}
+
+impl<F> LineParser for F
+where
+    F: Fn(&str) -> Option<LogEntry> + Send + Sync,
+{
+    fn parse(&self, line: &str) -> Option<LogEntry> {
+        self(line)
+    }
+}
+
+const LEVEL_KEYS: [&str; 4] = ["level", "lvl", "severity", "log.level"];
+const MESSAGE_KEYS: [&str; 3] = ["msg", "message", "text"];
+
+/// Move the first level and message keys found in `fields` into an entry
+fn entry(mut fields: BTreeMap<String, String>) -> LogEntry {
+    let level = LEVEL_KEYS
+        .iter()
+        .find_map(|key| fields.remove(*key))
+        .and_then(|level| Level::parse(&level));
+    let message = MESSAGE_KEYS
+        .iter()
+        .find_map(|key| fields.remove(*key))
+        .unwrap_or_default();
+    LogEntry {
+        level,
+        message,
+        fields,
+    }
+}
+
+/// JSON object lines like `{"level":"info","msg":"ready","port":8080}`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonParser;
+
+impl LineParser for JsonParser {
+    fn parse(&self, line: &str) -> Option<LogEntry> {
+        let object = match serde_json::from_str(line.trim()).ok()? {
+            serde_json::Value::Object(object) => object,
+            _ => return None,
+        };
+        let fields = object
+            .into_iter()
+            .map(|(key, value)| match value {
+                serde_json::Value::String(value) => (key, value),
+                value => (key, value.to_string()),
+            })
+            .collect();
+        Some(entry(fields))
+    }
+}
+
+/// logfmt lines like `level=info msg="listening on :80" port=80`.
+///
+/// Every whitespace separated token must be `key=value` or a bare `key`, which maps to `true`,
+/// and at least one must have a value, so plain sentences are rejected.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogfmtParser;
+
+impl LineParser for LogfmtParser {
+    fn parse(&self, line: &str) -> Option<LogEntry> {
+        let mut fields = BTreeMap::new();
+        let mut has_value = false;
+        let mut rest = line.trim();
+        while !rest.is_empty() {
+            let end = rest
+                .find(|c: char| c == '=' || c.is_whitespace())
+                .unwrap_or(rest.len());
+            let key = &rest[..end];
+            let valid = |c: char| c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | '/' | '@');
+            if key.is_empty() || !key.chars().all(valid) {
+                return None;
+            }
+            rest = &rest[end..];
+            let value = match rest.strip_prefix('=') {
+                Some(after) => {
+                    let (value, after) = logfmt_value(after)?;
+                    has_value = true;
+                    rest = after;
+                    value
+                }
+                None => "true".to_string(),
+            };
+            if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+                return None;
+            }
+            fields.insert(key.to_string(), value);
+            rest = rest.trim_start();
+        }
+        has_value.then(|| entry(fields))
+    }
+}
+
+/// Split a bare or double quoted logfmt value off `input`
+fn logfmt_value(input: &str) -> Option<(String, &str)> {
+    let Some(quoted) = input.strip_prefix('"') else {
+        let end = input.find(char::is_whitespace).unwrap_or(input.len());
+        return Some((input[..end].to_string(), &input[end..]));
+    };
+    let mut value = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &quoted[i + 1..])),
+            '\\' => match chars.next()?.1 {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                escaped => value.push(escaped),
+            },
+            c => value.push(c),
+        }
+    }
+    // Unterminated quote
+    None
+}
+
+/// Lines starting with a bracketed level like `[WARN] disk almost full`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PrefixParser;
+
+impl LineParser for PrefixParser {
+    fn parse(&self, line: &str) -> Option<LogEntry> {
+        let (level, message) = line.trim_start().strip_prefix('[')?.split_once(']')?;
+        Some(LogEntry {
+            level: Some(Level::parse(level)?),
+            message: message.trim().to_string(),
+            fields: BTreeMap::new(),
+        })
+    }
+}
+
+/// Tries each parser in order, using the first that recognizes the line.
+///
+/// The default tries [`JsonParser`], [`LogfmtParser`] then [`PrefixParser`].
+pub struct Parsers(Vec<Box<dyn LineParser>>);
+
+impl Parsers {
+    /// Chain without any parser, recognizing nothing
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Try `parser` after the ones added before
+    pub fn with(mut self, parser: impl LineParser + 'static) -> Self {
+        self.0.push(Box::new(parser));
+        self
+    }
+}
+
+impl Default for Parsers {
+    fn default() -> Self {
+        Self::new()
+            .with(JsonParser)
+            .with(LogfmtParser)
+            .with(PrefixParser)
+    }
+}
+
+impl LineParser for Parsers {
+    fn parse(&self, line: &str) -> Option<LogEntry> {
+        self.0.iter().find_map(|parser| parser.parse(line))
+    }
+}
+
+/// An [`Event`] with the [`LogEntry`] parsed from its line, if any
+#[derive(Debug)]
+pub struct Parsed {
+    /// The event as read, raw text included
+    pub event: Event,
+    /// `None` for non-line output and lines no parser recognized
+    pub entry: Option<LogEntry>,
+}
+
+impl Parsed {
+    /// Level of the parsed line, `None` sorting below every level
+    pub fn level(&self) -> Option<Level> {
+        self.entry.as_ref().and_then(|entry| entry.level)
+    }
+}
+
+/// Text of stdout and stderr lines, including those of [`Output::Stage`]
+fn line(output: &Output) -> Option<&str> {
+    match output {
+        Output::Out(line) | Output::Err(line) => Some(line),
+        Output::Stage(_, output) => line(output),
+        _ => None,
+    }
+}
+
+impl<'a> OutputStream<'a> {
+    /// Iterate over events with their lines parsed by `parser`, e.g. [`Parsers::default`].
+    ///
+    /// ```no_run
+    /// # use misc::process::{Level, Parsers, Process};
+    /// # let process = Process::new(&mut std::process::Command::new("server")).unwrap();
+    /// for parsed in process.stream().parsed(Parsers::default()) {
+    ///     if parsed.level() >= Some(Level::Warn) {
+    ///         eprintln!("{}", parsed.event.output);
+    ///     }
+    /// }
+    /// ```
+    pub fn parsed(self, parser: impl LineParser + 'a) -> impl Iterator<Item = Parsed> + 'a {
+        self.events().map(move |event| {
+            let entry = line(&event.output).and_then(|line| parser.parse(line));
+            Parsed { event, entry }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::{FakeProcess, FakeScript, OutputSource};
+
+    fn fields(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_json_lines() {
+        let entry = JsonParser
+            .parse(r#"{"level":"WARNING","msg":"slow","ms":120,"path":"/a"}"#)
+            .unwrap();
+
+        assert_eq!(entry.level, Some(Level::Warn));
+        assert_eq!(entry.message, "slow");
+        assert_eq!(entry.fields, fields(&[("ms", "120"), ("path", "/a")]));
+        assert!(JsonParser.parse("[1, 2]").is_none());
+        assert!(JsonParser.parse("plain text").is_none());
+    }
+
+    #[test]
+    fn parses_logfmt_lines() {
+        let entry = LogfmtParser
+            .parse(r#"level=info msg="listening on \"0.0.0.0\"" port=80 tls"#)
+            .unwrap();
+
+        assert_eq!(entry.level, Some(Level::Info));
+        assert_eq!(entry.message, r#"listening on "0.0.0.0""#);
+        assert_eq!(entry.fields, fields(&[("port", "80"), ("tls", "true")]));
+        assert!(LogfmtParser.parse("just a sentence").is_none());
+        assert!(LogfmtParser.parse("error: code=1").is_none());
+        assert!(LogfmtParser.parse(r#"msg="unterminated"#).is_none());
+    }
+
+    #[test]
+    fn parses_level_prefixes() {
+        let entry = PrefixParser.parse("[WARN] disk almost full").unwrap();
+
+        assert_eq!(entry.level, Some(Level::Warn));
+        assert_eq!(entry.message, "disk almost full");
+        assert!(PrefixParser.parse("[1/3] building").is_none());
+    }
+
+    #[test]
+    fn filters_stream_by_level() {
+        let fake = FakeProcess::spawn(
+            FakeScript::new()
+                .out(r#"{"level":"debug","msg":"starting"}"#)
+                .out("level=error msg=crashed")
+                .err("[WARN] retrying")
+                .out("plain"),
+        );
+
+        let parsed: Vec<Parsed> = fake.stream().parsed(Parsers::default()).collect();
+
+        let severe: Vec<&str> = parsed
+            .iter()
+            .filter(|p| p.level() >= Some(Level::Warn))
+            .map(|p| p.entry.as_ref().unwrap().message.as_str())
+            .collect();
+        assert_eq!(severe, ["crashed", "retrying"]);
+        assert!(matches!(&parsed[3].event.output, Output::Out(l) if l == "plain"));
+        assert!(parsed[3].entry.is_none());
+        assert!(matches!(parsed[4].event.output, Output::Exit(_)));
+    }
+}
@@ -0,0 +1,254 @@
+//! Shell-style pipelines of [`Process`] stages
+use super::event::{Cursor, OutputSender};
+use super::{Exit, Output, OutputReceiver, OutputStream, Process, ProcessOptions, Shared};
+use crossbeam_channel::unbounded;
+use std::io;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// Commands connected stdout to stdin like `a | b | c`.
+///
+/// The last stage's stdout arrives as plain [`Output::Out`]. Every stage's stderr and exit
+/// arrive as [`Output::Stage`] tagged with the stage index. The stream ends with one
+/// [`Output::Exit`] following pipefail rules, see [`Pipeline::wait`].
+pub struct Pipeline {
+    rx: OutputReceiver,
+    stages: Vec<Arc<Shared>>,
+    handle: Option<JoinHandle<()>>,
+    /// Shared by its streams, see [`OutputStream::ordered`]
+    cursor: Cursor,
+}
+
+impl Pipeline {
+    /// Spawn `commands` in order, each reading the previous one's stdout
+    pub fn spawn(commands: impl IntoIterator<Item = Command>) -> io::Result<Pipeline> {
+        let mut processes: Vec<Process> = Vec::new();
+        let mut stdin = None;
+        let mut commands = commands.into_iter().peekable();
+        while let Some(mut command) = commands.next() {
+            let (reader, writer) = match commands.peek() {
+                Some(_) => io::pipe().map(|(r, w)| (Some(r), Some(Stdio::from(w))))?,
+                None => (None, None),
+            };
+            let input = stdin.take().map(Stdio::from);
+            match Process::spawn(&mut command, ProcessOptions::default(), input, writer) {
+                Ok(process) => processes.push(process),
+                Err(e) => {
+                    for process in processes {
+                        process.kill().ok();
+                    }
+                    return Err(e);
+                }
+            }
+            // Dropping the command releases our copies of the pipe ends
+            stdin = reader;
+        }
+        if processes.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "pipeline needs at least one command",
+            ));
+        }
+
+        let (tx, rx) = unbounded();
+        let tx = OutputSender::new(tx);
+        let stages = processes.iter().map(|p| p.shared.clone()).collect();
+        let last = processes.len() - 1;
+        let forwarders: Vec<_> = processes
+            .into_iter()
+            .enumerate()
+            .map(|(index, process)| {
+                let tx = tx.clone();
+                thread::spawn(move || forward(index, index == last, process, tx))
+            })
+            .collect();
+        let handle = thread::spawn(move || {
+            let exits = forwarders
+                .into_iter()
+                .map(|forwarder| {
+                    forwarder.join().unwrap_or_else(|_| {
+                        Err(io::Error::other("pipeline forwarding thread panicked"))
+                    })
+                })
+                .collect();
+            tx.send(Output::Exit(pipefail(exits))).ok();
+        });
+
+        Ok(Pipeline {
+            rx,
+            stages,
+            handle: Some(handle),
+            cursor: Cursor::default(),
+        })
+    }
+
+    /// Get iteratorable stream of outputs across all stages
+    pub fn stream(&self) -> OutputStream<'_> {
+        OutputStream {
+            rx: &self.rx,
+            reorder: None,
+            sequential: false,
+            cursor: Some(&self.cursor),
+            exit: false,
+        }
+    }
+
+    /// Number of stages
+    pub fn len(&self) -> usize {
+        self.stages.len()
+    }
+
+    /// Whether the pipeline has no stages, which [`Pipeline::spawn`] never returns
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// Block until every stage exited and return the exit of the rightmost stage that failed,
+    /// or of the last stage when all succeeded
+    pub fn wait(&self) -> io::Result<Exit> {
+        pipefail(self.stages.iter().map(|s| s.state.wait()).collect())
+    }
+
+    /// Kill every stage with SIGKILL and wait for them to be reaped
+    pub fn kill(mut self) -> io::Result<Exit> {
+        for stage in &self.stages {
+            stage.signal(libc::SIGKILL)?;
+        }
+        if let Some(handle) = self.handle.take() {
+            handle.join().ok();
+        }
+        self.wait()
+    }
+}
+
+impl Drop for Pipeline {
+    /// Kill stages still running and wait for them to be reaped, like dropping a [`Process`]
+    fn drop(&mut self) {
+        for stage in &self.stages {
+            if stage.signal(libc::SIGKILL).is_ok() {
+                stage.state.wait().ok();
+            }
+        }
+    }
+}
+
+/// Forward one stage's output, tagging everything but the last stage's stdout
+fn forward(index: usize, last: bool, process: Process, tx: OutputSender) -> io::Result<Exit> {
+    let mut exit = Err(io::Error::other("stage output ended without exit"));
+    for output in process.stream() {
+        let output = match output {
+            Output::Exit(result) => {
+                let tagged = match &result {
+                    Ok(status) => Output::Exit(Ok(*status)),
+                    Err(e) => Output::Exit(Err(io::Error::new(e.kind(), e.to_string()))),
+                };
+                exit = result;
+                Output::Stage(index, Box::new(tagged))
+            }
+            output @ (Output::Out(_) | Output::OutBytes(_)) if last => output,
+            output => Output::Stage(index, Box::new(output)),
+        };
+        tx.send(output).ok();
+    }
+    exit
+}
+
+/// Exit of the rightmost failed stage, or of the last stage when all succeeded
+fn pipefail(exits: Vec<io::Result<Exit>>) -> io::Result<Exit> {
+    let failed = exits
+        .iter()
+        .rposition(|exit| !matches!(exit, Ok(exit) if exit.success()));
+    let index = failed.unwrap_or(exits.len().saturating_sub(1));
+    exits
+        .into_iter()
+        .nth(index)
+        .unwrap_or_else(|| Err(io::Error::other("pipeline has no stages")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::sys;
+
+    fn sh(script: &str) -> Command {
+        let mut command = Command::new("sh");
+        command.args(["-c", script]);
+        command
+    }
+
+    #[test]
+    fn pipes_stages_together() {
+        let pipeline = Pipeline::spawn([
+            sh("printf 'b\\na\\nc\\n'; echo note >&2"),
+            Command::new("sort"),
+            sh("tr a-z A-Z"),
+        ])
+        .unwrap();
+
+        let outputs: Vec<Output> = pipeline.stream().collect();
+
+        let stdout: Vec<&str> = outputs
+            .iter()
+            .filter_map(|o| match o {
+                Output::Out(line) => Some(line.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(stdout, ["A", "B", "C"]);
+        assert!(outputs.iter().any(
+            |o| matches!(o, Output::Stage(0, e) if matches!(&**e, Output::Err(l) if l == "note"))
+        ));
+        let stage_exits = outputs
+            .iter()
+            .filter(|o| matches!(o, Output::Stage(_, e) if matches!(**e, Output::Exit(_))))
+            .count();
+        assert_eq!(stage_exits, 3);
+        assert!(matches!(
+            outputs.last(),
+            Some(Output::Exit(Ok(Exit { code: Some(0), .. })))
+        ));
+    }
+
+    #[test]
+    fn fails_with_rightmost_failed_stage() {
+        let pipeline =
+            Pipeline::spawn([sh("echo x; exit 3"), sh("cat; exit 4"), sh("cat")]).unwrap();
+
+        assert_eq!(pipeline.wait().unwrap().code, Some(4));
+        assert!(matches!(
+            pipeline.stream().last(),
+            Some(Output::Exit(Ok(Exit { code: Some(4), .. })))
+        ));
+    }
+
+    #[test]
+    fn upstream_sees_closed_pipe() {
+        let pipeline = Pipeline::spawn([Command::new("yes"), sh("head -n 2")]).unwrap();
+
+        let exit = pipeline.stream().last();
+
+        // `yes` dies of SIGPIPE once `head` exits, which pipefail reports
+        assert!(matches!(
+            exit,
+            Some(Output::Exit(Ok(Exit {
+                signal: Some(libc::SIGPIPE),
+                ..
+            })))
+        ));
+    }
+
+    #[test]
+    fn drop_kills_every_stage() {
+        let pipeline = Pipeline::spawn([sh("exec sleep 30"), sh("exec sleep 30")]).unwrap();
+        let pids: Vec<u32> = pipeline
+            .stages
+            .iter()
+            .map(|s| s.child.lock().unwrap().id())
+            .collect();
+
+        drop(pipeline);
+
+        assert!(pids.iter().all(|pid| sys::signal(*pid, 0).is_err()));
+    }
+}
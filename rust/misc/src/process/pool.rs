@@ -0,0 +1,329 @@
+//! Run many [`Process`] jobs with bounded concurrency
+use super::{Exit, Output, Process, Shared};
+use crossbeam_channel::{select, unbounded, Receiver, Sender};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::io;
+use std::process::Command;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Options controlling how a [`ProcessPool`] schedules its jobs
+#[derive(Debug, Clone, Copy)]
+pub struct PoolOptions {
+    /// Jobs running at the same time, at least one
+    pub concurrency: usize,
+    /// Kill running jobs and skip queued ones once any job fails
+    pub fail_fast: bool,
+}
+
+impl Default for PoolOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: thread::available_parallelism().map_or(1, |n| n.get()),
+            fail_fast: false,
+        }
+    }
+}
+
+/// How a pool job ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobStatus {
+    /// Exited with code 0
+    Succeeded(Exit),
+    /// Exited with another code or was killed by a signal
+    Failed(Exit),
+    /// Could not be spawned or reaped
+    Error(String),
+    /// Skipped or killed after another job failed with [`PoolOptions::fail_fast`], or after
+    /// [`ProcessPool::cancel`]
+    Cancelled,
+}
+
+/// Outcome of one pool job
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobSummary {
+    /// Position of the command passed to [`ProcessPool::spawn`]
+    pub id: usize,
+    pub status: JobStatus,
+    /// Time from spawn to exit, zero for jobs that never ran
+    pub duration: Duration,
+}
+
+/// Outcome of every job of a [`ProcessPool`], ordered by job id
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolSummary {
+    pub jobs: Vec<JobSummary>,
+    /// Time from spawning the pool until the last job finished
+    pub elapsed: Duration,
+}
+
+impl PoolSummary {
+    fn count(&self, f: impl Fn(&JobStatus) -> bool) -> usize {
+        self.jobs.iter().filter(|job| f(&job.status)).count()
+    }
+
+    pub fn succeeded(&self) -> usize {
+        self.count(|s| matches!(s, JobStatus::Succeeded(_)))
+    }
+
+    /// Jobs that failed or could not run
+    pub fn failed(&self) -> usize {
+        self.count(|s| matches!(s, JobStatus::Failed(_) | JobStatus::Error(_)))
+    }
+
+    pub fn cancelled(&self) -> usize {
+        self.count(|s| matches!(s, JobStatus::Cancelled))
+    }
+
+    /// Whether every job succeeded
+    pub fn success(&self) -> bool {
+        self.succeeded() == self.jobs.len()
+    }
+}
+
+impl fmt::Display for PoolSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} succeeded, {} failed, {} cancelled in {:?}",
+            self.succeeded(),
+            self.failed(),
+            self.cancelled(),
+            self.elapsed
+        )
+    }
+}
+
+/// Runs [`Command`]s with at most [`PoolOptions::concurrency`] at once, merging their output
+/// into one stream tagged with the job id.
+///
+/// Every job's output ends with its own [`Output::Exit`], including jobs that failed to spawn.
+/// Jobs that were cancelled before they started produce no output.
+pub struct ProcessPool {
+    rx: Receiver<(usize, Output)>,
+    stop: Sender<()>,
+    handle: Option<JoinHandle<PoolSummary>>,
+}
+
+impl ProcessPool {
+    /// Start running `commands`, job ids being their positions
+    pub fn spawn(commands: impl IntoIterator<Item = Command>, options: PoolOptions) -> Self {
+        let (tx, rx) = unbounded();
+        let (stop, stop_rx) = unbounded();
+        let queue = commands.into_iter().enumerate().collect();
+        let handle = thread::spawn(move || schedule(queue, options, tx, stop_rx));
+
+        ProcessPool {
+            rx,
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    /// Merged `(job_id, output)` stream, ending once every job finished
+    pub fn stream(&self) -> impl Iterator<Item = (usize, Output)> + '_ {
+        self.rx.iter()
+    }
+
+    /// Kill running jobs and skip queued ones
+    pub fn cancel(&self) {
+        self.stop.send(()).ok();
+    }
+
+    /// Block until every job finished or was cancelled
+    pub fn wait(mut self) -> PoolSummary {
+        self.join()
+    }
+
+    fn join(&mut self) -> PoolSummary {
+        match self.handle.take().map(JoinHandle::join) {
+            Some(Ok(summary)) => summary,
+            _ => PoolSummary {
+                jobs: Vec::new(),
+                elapsed: Duration::ZERO,
+            },
+        }
+    }
+}
+
+impl Drop for ProcessPool {
+    fn drop(&mut self) {
+        self.cancel();
+        self.join();
+    }
+}
+
+/// A running job as seen by the scheduler
+struct Running {
+    shared: Arc<Shared>,
+    started: Instant,
+}
+
+fn schedule(
+    mut queue: VecDeque<(usize, Command)>,
+    options: PoolOptions,
+    tx: Sender<(usize, Output)>,
+    stop: Receiver<()>,
+) -> PoolSummary {
+    let started = Instant::now();
+    let (done_tx, done) = unbounded::<(usize, io::Result<Exit>)>();
+    let mut running: HashMap<usize, Running> = HashMap::new();
+    let mut killed = HashSet::new();
+    let mut jobs = Vec::new();
+    let mut cancelled = false;
+
+    loop {
+        while !cancelled && running.len() < options.concurrency.max(1) {
+            let Some((id, mut command)) = queue.pop_front() else {
+                break;
+            };
+            match Process::new(&mut command) {
+                Ok(process) => {
+                    let job = Running {
+                        shared: process.shared.clone(),
+                        started: Instant::now(),
+                    };
+                    running.insert(id, job);
+                    let (tx, done_tx) = (tx.clone(), done_tx.clone());
+                    thread::spawn(move || {
+                        let mut exit = Err(io::Error::other("job output ended without exit"));
+                        for output in process.stream() {
+                            if let Output::Exit(result) = &output {
+                                exit = match result {
+                                    Ok(status) => Ok(*status),
+                                    Err(e) => Err(io::Error::new(e.kind(), e.to_string())),
+                                };
+                            }
+                            tx.send((id, output)).ok();
+                        }
+                        done_tx.send((id, exit)).ok();
+                    });
+                }
+                Err(e) => {
+                    let status = JobStatus::Error(e.to_string());
+                    tx.send((id, Output::Exit(Err(e)))).ok();
+                    jobs.push(JobSummary {
+                        id,
+                        status,
+                        duration: Duration::ZERO,
+                    });
+                    cancelled |= options.fail_fast;
+                }
+            }
+        }
+        if cancelled {
+            for (id, job) in &running {
+                if killed.insert(*id) {
+                    job.shared.signal(libc::SIGKILL).ok();
+                }
+            }
+        }
+        if running.is_empty() {
+            break;
+        }
+
+        select! {
+            recv(done) -> finished => {
+                let Ok((id, exit)) = finished else { break };
+                let Some(job) = running.remove(&id) else { continue };
+                let status = match exit {
+                    _ if killed.contains(&id) => JobStatus::Cancelled,
+                    Ok(exit) if exit.success() => JobStatus::Succeeded(exit),
+                    Ok(exit) => JobStatus::Failed(exit),
+                    Err(e) => JobStatus::Error(e.to_string()),
+                };
+                cancelled |= options.fail_fast && !matches!(status, JobStatus::Succeeded(_));
+                jobs.push(JobSummary {
+                    id,
+                    status,
+                    duration: job.started.elapsed(),
+                });
+            }
+            recv(stop) -> _ => cancelled = true,
+        }
+    }
+
+    jobs.extend(queue.into_iter().map(|(id, _)| JobSummary {
+        id,
+        status: JobStatus::Cancelled,
+        duration: Duration::ZERO,
+    }));
+    jobs.sort_unstable_by_key(|job| job.id);
+    PoolSummary {
+        jobs,
+        elapsed: started.elapsed(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> Command {
+        let mut command = Command::new("sh");
+        command.args(["-c", script]);
+        command
+    }
+
+    #[test]
+    fn limits_concurrency_and_merges_output() {
+        let commands = (0..6).map(|i| sh(&format!("echo job {i}; sleep 0.1")));
+        let options = PoolOptions {
+            concurrency: 2,
+            fail_fast: false,
+        };
+        let pool = ProcessPool::spawn(commands, options);
+
+        let outputs: Vec<(usize, Output)> = pool.stream().collect();
+        let summary = pool.wait();
+
+        for id in 0..6 {
+            let expected = format!("job {id}");
+            assert!(outputs
+                .iter()
+                .any(|(job, o)| *job == id && matches!(o, Output::Out(l) if *l == expected)));
+        }
+        assert_eq!(summary.succeeded(), 6);
+        assert!(summary.success());
+        // Three rounds of two jobs sleeping 0.1s each
+        assert!(summary.elapsed >= Duration::from_millis(300));
+        assert!(summary.jobs.iter().all(|job| job.duration > Duration::ZERO));
+    }
+
+    #[test]
+    fn fail_fast_cancels_remaining_jobs() {
+        let commands = vec![sh("exit 2"), sh("exec sleep 30"), sh("echo never")];
+        let options = PoolOptions {
+            concurrency: 2,
+            fail_fast: true,
+        };
+        let pool = ProcessPool::spawn(commands, options);
+
+        let outputs: Vec<(usize, Output)> = pool.stream().collect();
+        let summary = pool.wait();
+
+        assert!(outputs.iter().all(|(job, _)| *job != 2));
+        assert!(matches!(
+            summary.jobs[0].status,
+            JobStatus::Failed(Exit { code: Some(2), .. })
+        ));
+        assert_eq!(summary.jobs[1].status, JobStatus::Cancelled);
+        assert_eq!(summary.jobs[2].status, JobStatus::Cancelled);
+        assert_eq!((summary.failed(), summary.cancelled()), (1, 2));
+    }
+
+    #[test]
+    fn reports_spawn_errors() {
+        let pool = ProcessPool::spawn(
+            [Command::new("/nonexistent/tool"), sh("true")],
+            PoolOptions::default(),
+        );
+
+        let summary = pool.wait();
+
+        assert!(matches!(summary.jobs[0].status, JobStatus::Error(_)));
+        assert!(matches!(summary.jobs[1].status, JobStatus::Succeeded(_)));
+    }
+}
@@ -0,0 +1,287 @@
+//! Progress detection in child output, aware of `\r` redrawn progress bars
+use super::timeout::Activity;
+use super::{Output, OutputSender, ProcessHandle};
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read};
+use std::sync::Arc;
+use std::thread;
+
+/// Progress reported by the child, see [`ProgressOptions`]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Progress {
+    pub current: Option<u64>,
+    pub total: Option<u64>,
+    /// Reported percentage, or computed from `current` and `total`
+    pub percent: Option<f64>,
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.current, self.total, self.percent) {
+            (Some(current), Some(total), Some(percent)) => {
+                write!(f, "{current}/{total} ({percent:.1}%)")
+            }
+            (Some(current), Some(total), None) => write!(f, "{current}/{total}"),
+            (_, _, Some(percent)) => write!(f, "{percent:.1}%"),
+            (Some(current), None, None) => write!(f, "{current}"),
+            _ => f.write_str("unknown"),
+        }
+    }
+}
+
+/// Patterns turning lines of stdout and stderr into [`Output::Progress`], tried in order.
+///
+/// Lines are also split on `\r`, so a bar redrawn in place yields one update per redraw.
+/// Such redrawn lines are replaced by their [`Output::Progress`] when a pattern matches,
+/// while `\n` terminated lines are kept and followed by it. Requires [`ReadMode::Lines`].
+///
+/// The default recognizes counts like `12/300` then percentages like `45%`, covering
+/// tqdm-style bars such as ` 45%|████▌     | 45/100 [00:01<00:01]`.
+///
+/// [`ReadMode::Lines`]: super::ReadMode::Lines
+#[derive(Debug, Clone)]
+pub struct ProgressOptions {
+    patterns: Vec<Regex>,
+}
+
+impl ProgressOptions {
+    /// Options without any pattern, add them with [`ProgressOptions::pattern`]
+    pub fn new() -> Self {
+        Self {
+            patterns: Vec::new(),
+        }
+    }
+
+    /// Try `pattern` after the ones added before. Its named groups `current`, `total` and
+    /// `percent` fill the matching [`Progress`] fields, and at least one must match.
+    pub fn pattern(mut self, pattern: Regex) -> Self {
+        self.patterns.push(pattern);
+        self
+    }
+
+    /// Recognize counts like `12/300` or `12 / 300`
+    pub fn fraction(self) -> Self {
+        self.pattern(Regex::new(r"\b(?P<current>\d+)\s?/\s?(?P<total>\d+)\b").unwrap())
+    }
+
+    /// Recognize percentages like `45%` or `45.5 %`
+    pub fn percent(self) -> Self {
+        self.pattern(Regex::new(r"(?P<percent>\d+(?:\.\d+)?)\s?%").unwrap())
+    }
+
+    /// Progress of the first pattern matching `line`
+    pub(super) fn extract(&self, line: &str) -> Option<Progress> {
+        self.patterns
+            .iter()
+            .filter_map(|pattern| pattern.captures(line))
+            .find_map(|captures| progress(&captures))
+    }
+}
+
+impl Default for ProgressOptions {
+    fn default() -> Self {
+        Self::new().fraction().percent()
+    }
+}
+
+/// Build progress from named groups, rejecting counts beyond their total and percentages
+/// above 100
+fn progress(captures: &Captures) -> Option<Progress> {
+    let group = |name| captures.name(name).map(|m| m.as_str());
+    let current = group("current").and_then(|c| c.parse::<u64>().ok());
+    let total = group("total").and_then(|t| t.parse::<u64>().ok());
+    let percent = group("percent")
+        .and_then(|p| p.parse::<f64>().ok())
+        .or(match (current, total) {
+            (Some(current), Some(total)) if total > 0 => {
+                Some(current as f64 * 100.0 / total as f64)
+            }
+            _ => None,
+        });
+    match (current, total, percent) {
+        (None, None, None) => None,
+        (Some(current), Some(total), _) if current > total => None,
+        (_, _, Some(percent)) if percent > 100.0 => None,
+        _ => Some(Progress {
+            current,
+            total,
+            percent,
+        }),
+    }
+}
+
+/// How a segment read by [`read_segment`] ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Ending {
+    /// `\n`, or `\r\n`
+    Newline,
+    /// A lone `\r`, the line is about to be redrawn
+    Return,
+    /// End of stream without terminator
+    Eof,
+}
+
+/// Read into `buf` up to and excluding the next `\r` or `\n`, returning `None` at end of
+/// stream. `\r\n` counts as one newline. When the `\n` was not read yet the `\r` ends the
+/// segment and the following call, passed `after_return`, skips the `\n`.
+pub(super) fn read_segment<R: BufRead>(
+    reader: &mut R,
+    buf: &mut Vec<u8>,
+    after_return: bool,
+) -> io::Result<Option<Ending>> {
+    let mut skip_newline = after_return;
+    let mut read = false;
+    loop {
+        let available = match reader.fill_buf() {
+            Ok(available) => available,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if available.is_empty() {
+            return Ok(read.then_some(Ending::Eof));
+        }
+        if std::mem::take(&mut skip_newline) && available[0] == b'\n' {
+            reader.consume(1);
+            continue;
+        }
+        read = true;
+        match available.iter().position(|b| matches!(b, b'\n' | b'\r')) {
+            Some(end) => {
+                buf.extend_from_slice(&available[..end]);
+                let (ending, len) = match &available[end..] {
+                    [b'\r', b'\n', ..] => (Ending::Newline, 2),
+                    [b'\r', ..] => (Ending::Return, 1),
+                    _ => (Ending::Newline, 1),
+                };
+                reader.consume(end + len);
+                return Ok(Some(ending));
+            }
+            None => {
+                let len = available.len();
+                buf.extend_from_slice(available);
+                reader.consume(len);
+            }
+        }
+    }
+}
+
+/// Read lines split on `\n` and `\r`, sending progress extracted with `options`
+pub(super) fn spawn_reader<R: Read + Send + 'static>(
+    is_stdout: bool,
+    out: R,
+    options: ProgressOptions,
+    tx: OutputSender,
+    activity: Arc<Activity>,
+) -> ProcessHandle {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(out);
+        let mut buf = Vec::new();
+        let mut after_return = false;
+        loop {
+            buf.clear();
+            let ending = match read_segment(&mut reader, &mut buf, after_return) {
+                Ok(Some(ending)) => ending,
+                Ok(None) => break,
+                Err(e) => {
+                    tx.send(Output::Err(e.to_string())).ok();
+                    break;
+                }
+            };
+            activity.touch();
+            after_return = ending == Ending::Return;
+
+            let line = String::from_utf8_lossy(&buf).into_owned();
+            let progress = options.extract(&line);
+            // A redrawn line is transient, keep it only when it carries no progress
+            let keep = match ending {
+                Ending::Return => progress.is_none() && !line.is_empty(),
+                Ending::Newline | Ending::Eof => true,
+            };
+            if keep {
+                tx.send(match is_stdout {
+                    true => Output::Out(line),
+                    false => Output::Err(line),
+                })
+                .ok();
+            }
+            if let Some(progress) = progress {
+                tx.send(Output::Progress(progress)).ok();
+            }
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segments(input: &[u8]) -> Vec<(String, Ending)> {
+        let mut reader = input;
+        let mut segments = Vec::new();
+        let mut after_return = false;
+        let mut buf = Vec::new();
+        while let Some(ending) = read_segment(&mut reader, &mut buf, after_return).unwrap() {
+            segments.push((String::from_utf8(buf.clone()).unwrap(), ending));
+            after_return = ending == Ending::Return;
+            buf.clear();
+        }
+        segments
+    }
+
+    #[test]
+    fn splits_on_carriage_returns() {
+        let segments = segments(b"a\r\nb\rc\n\rtail");
+
+        assert_eq!(
+            segments,
+            [
+                ("a".to_string(), Ending::Newline),
+                ("b".to_string(), Ending::Return),
+                ("c".to_string(), Ending::Newline),
+                ("".to_string(), Ending::Return),
+                ("tail".to_string(), Ending::Eof),
+            ]
+        );
+    }
+
+    #[test]
+    fn extracts_default_formats() {
+        let options = ProgressOptions::default();
+        let progress = |line| options.extract(line);
+
+        assert_eq!(
+            progress(" 45%|████▌     | 45/100 [00:01<00:01]"),
+            Some(Progress {
+                current: Some(45),
+                total: Some(100),
+                percent: Some(45.0),
+            })
+        );
+        assert_eq!(
+            progress("Downloading... 12.5%"),
+            Some(Progress {
+                current: None,
+                total: None,
+                percent: Some(12.5),
+            })
+        );
+        assert_eq!(progress("date 2022/04 ok 250%"), None);
+        assert_eq!(progress("nothing here"), None);
+    }
+
+    #[test]
+    fn extracts_custom_patterns() {
+        let options = ProgressOptions::new()
+            .pattern(Regex::new(r"step (?P<current>\d+) of (?P<total>\d+)").unwrap());
+
+        let progress = options.extract("step 3 of 4").unwrap();
+
+        assert_eq!((progress.current, progress.total), (Some(3), Some(4)));
+        assert_eq!(progress.percent, Some(75.0));
+        assert_eq!(progress.to_string(), "3/4 (75.0%)");
+        assert_eq!(options.extract("50%"), None);
+    }
+}
@@ -0,0 +1,168 @@
+//! Pseudo-terminal mode for [`super::Process`]
+use std::fs::File;
+use std::io::{self, Read};
+
+/// Run the child on a pseudo-terminal instead of pipes, so it behaves as if attached to a
+/// terminal. stdout and stderr share the terminal and both arrive as stdout, split according
+/// to [`ProcessOptions::read_mode`] and [`ProcessOptions::progress`] like piped output.
+///
+/// [`ProcessOptions::read_mode`]: super::ProcessOptions::read_mode
+/// [`ProcessOptions::progress`]: super::ProcessOptions::progress
+#[derive(Debug, Clone, Copy)]
+pub struct PtyOptions {
+    /// Terminal height in rows
+    pub rows: u16,
+    /// Terminal width in columns
+    pub cols: u16,
+    /// Escape sequences kept in or removed from terminal output
+    pub lines: PtyLines,
+}
+
+impl Default for PtyOptions {
+    fn default() -> Self {
+        Self {
+            rows: 24,
+            cols: 80,
+            lines: PtyLines::Raw,
+        }
+    }
+}
+
+/// Filtering applied to pseudo-terminal output before it is split
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PtyLines {
+    /// Keep escape sequences and carriage returns as the child wrote them. The terminal turns
+    /// `\n` into `\r\n`, which [`ReadMode::Lines`] strips and other modes keep.
+    ///
+    /// [`ReadMode::Lines`]: super::ReadMode::Lines
+    #[default]
+    Raw,
+    /// Strip ANSI escape sequences such as colours and cursor movement
+    StripAnsi,
+}
+
+/// Terminal master read as a stream ending once the child side is closed
+pub(super) struct TerminalReader {
+    master: File,
+    strip: Option<AnsiStripper>,
+    buf: Vec<u8>,
+}
+
+impl TerminalReader {
+    pub(super) fn new(master: File, lines: PtyLines) -> Self {
+        Self {
+            master,
+            strip: (lines == PtyLines::StripAnsi).then(AnsiStripper::default),
+            buf: Vec::new(),
+        }
+    }
+}
+
+impl Read for TerminalReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let Some(strip) = &mut self.strip else {
+            return read_master(&mut self.master, out);
+        };
+        // Serve what a previous read stripped before reading more
+        while self.buf.is_empty() {
+            let mut chunk = [0; 4096];
+            let len = read_master(&mut self.master, &mut chunk)?;
+            if len == 0 {
+                return Ok(0);
+            }
+            strip.feed(&chunk[..len], &mut self.buf);
+        }
+        let len = out.len().min(self.buf.len());
+        out[..len].copy_from_slice(&self.buf[..len]);
+        self.buf.drain(..len);
+        Ok(len)
+    }
+}
+
+fn read_master(master: &mut File, out: &mut [u8]) -> io::Result<usize> {
+    match master.read(out) {
+        // Linux reports EIO on the master once every slave descriptor is closed.
+        Err(e) if e.raw_os_error() == Some(libc::EIO) => Ok(0),
+        read => read,
+    }
+}
+
+/// Where [`AnsiStripper`] stands within an escape sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Escape {
+    #[default]
+    None,
+    /// After `ESC`
+    Start,
+    /// Inside `ESC [`, until a final byte
+    Csi,
+    /// Inside `ESC ]`, until `BEL` or `ESC \`
+    Osc,
+    /// `ESC` inside an OSC, possibly starting its `ESC \` terminator
+    OscEsc,
+    /// Inside an nF sequence, until a byte past the intermediates
+    Nf,
+}
+
+/// Removes escape sequences, including ones split across several reads
+#[derive(Debug, Default)]
+struct AnsiStripper {
+    escape: Escape,
+}
+
+impl AnsiStripper {
+    fn feed(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        for &byte in input {
+            self.escape = match (self.escape, byte) {
+                (Escape::None, 0x1b) => Escape::Start,
+                (Escape::None, byte) => {
+                    out.push(byte);
+                    Escape::None
+                }
+                (Escape::Start, b'[') => Escape::Csi,
+                (Escape::Start, b']') => Escape::Osc,
+                // nF sequences such as charset selection: intermediates then one final byte.
+                (Escape::Start | Escape::Nf, 0x20..=0x2f) => Escape::Nf,
+                (Escape::Csi, 0x40..=0x7e) => Escape::None,
+                (Escape::Csi, _) => Escape::Csi,
+                (Escape::Osc | Escape::OscEsc, 0x07) => Escape::None,
+                (Escape::Osc | Escape::OscEsc, 0x1b) => Escape::OscEsc,
+                (Escape::OscEsc, b'\\') => Escape::None,
+                (Escape::Osc | Escape::OscEsc, _) => Escape::Osc,
+                // The byte after a lone ESC, or the final byte of an nF sequence
+                (Escape::Start | Escape::Nf, _) => Escape::None,
+            };
+        }
+    }
+}
+
+/// Remove CSI (`ESC [ ... final`), OSC (`ESC ] ... BEL|ST`), nF and two-byte escape sequences.
+pub fn strip_ansi(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len());
+    AnsiStripper::default().feed(input, &mut out);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_colour_and_title_sequences() {
+        let input = b"\x1b[1;31merror\x1b[0m: \x1b]0;title\x07done\x1b]2;t\x1b\\!\x1b(B";
+
+        assert_eq!(strip_ansi(input), b"error: done!");
+    }
+
+    #[test]
+    fn strips_sequences_split_across_reads() {
+        let mut stripper = AnsiStripper::default();
+        let mut out = Vec::new();
+
+        for chunk in [&b"a\x1b"[..], b"[1;3", b"1mb\x1b]0;ti", b"tle\x1b", b"\\c"] {
+            stripper.feed(chunk, &mut out);
+        }
+
+        assert_eq!(out, b"abc");
+    }
+}
@@ -0,0 +1,345 @@
+//! Stable JSON-lines encoding of [`Event`]s for shipping and replaying output
+use super::tee::timestamp;
+use super::{Event, Exit, Lifecycle, Output, Progress};
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, Write};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Serialized form of an [`Event`], written as one JSON object per line.
+///
+/// ```json
+/// {"seq":0,"elapsed_us":812,"timestamp":"2022-04-02T10:20:30.123Z","pid":4242,"kind":"line","stream":"stdout","text":"hello"}
+/// {"seq":1,"elapsed_us":950,"timestamp":"2022-04-02T10:20:30.123Z","pid":4242,"kind":"exit","exit":{"code":0,"signal":null,...},"message":"exited with code 0"}
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub seq: u64,
+    /// Microseconds since the process was spawned
+    pub elapsed_us: u64,
+    /// Wall-clock UTC time the event was read, informational only
+    pub timestamp: String,
+    pub pid: Option<u32>,
+    #[serde(flatten)]
+    pub kind: RecordKind,
+}
+
+/// What a [`Record`] carries, tagged by `kind`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RecordKind {
+    /// [`Output::Out`] or [`Output::Err`]
+    Line {
+        stream: Stream,
+        text: String,
+    },
+    /// [`Output::OutBytes`] or [`Output::ErrBytes`]
+    Bytes {
+        stream: Stream,
+        bytes: Vec<u8>,
+    },
+    /// [`Output::Exit`] with the [`Exit`] and its human readable message
+    Exit {
+        exit: Exit,
+        message: String,
+    },
+    /// [`Output::Exit`] without exit status
+    Error {
+        error: String,
+        message: String,
+    },
+    Lifecycle {
+        lifecycle: Lifecycle,
+    },
+    Dropped {
+        count: u64,
+    },
+    /// [`Output::Stage`] wrapping the stage's own record
+    Stage {
+        index: usize,
+        record: Box<RecordKind>,
+    },
+    Progress {
+        progress: Progress,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl RecordKind {
+    fn new(output: &Output) -> Self {
+        match output {
+            Output::Out(text) => RecordKind::Line {
+                stream: Stream::Stdout,
+                text: text.clone(),
+            },
+            Output::Err(text) => RecordKind::Line {
+                stream: Stream::Stderr,
+                text: text.clone(),
+            },
+            Output::OutBytes(bytes) => RecordKind::Bytes {
+                stream: Stream::Stdout,
+                bytes: bytes.clone(),
+            },
+            Output::ErrBytes(bytes) => RecordKind::Bytes {
+                stream: Stream::Stderr,
+                bytes: bytes.clone(),
+            },
+            Output::Exit(Ok(exit)) => RecordKind::Exit {
+                exit: *exit,
+                message: exit.to_string(),
+            },
+            Output::Exit(Err(e)) => RecordKind::Error {
+                error: format!("{:?}", e.kind()),
+                message: e.to_string(),
+            },
+            Output::Lifecycle(lifecycle) => RecordKind::Lifecycle {
+                lifecycle: lifecycle.clone(),
+            },
+            Output::Dropped(count) => RecordKind::Dropped { count: *count },
+            Output::Stage(index, output) => RecordKind::Stage {
+                index: *index,
+                record: Box::new(RecordKind::new(output)),
+            },
+            Output::Progress(progress) => RecordKind::Progress {
+                progress: *progress,
+            },
+        }
+    }
+
+    fn into_output(self) -> Output {
+        match self {
+            RecordKind::Line {
+                stream: Stream::Stdout,
+                text,
+            } => Output::Out(text),
+            RecordKind::Line {
+                stream: Stream::Stderr,
+                text,
+            } => Output::Err(text),
+            RecordKind::Bytes {
+                stream: Stream::Stdout,
+                bytes,
+            } => Output::OutBytes(bytes),
+            RecordKind::Bytes {
+                stream: Stream::Stderr,
+                bytes,
+            } => Output::ErrBytes(bytes),
+            RecordKind::Exit { exit, .. } => Output::Exit(Ok(exit)),
+            RecordKind::Error { error, message } => {
+                Output::Exit(Err(io::Error::new(error_kind(&error), message)))
+            }
+            RecordKind::Lifecycle { lifecycle } => Output::Lifecycle(lifecycle),
+            RecordKind::Dropped { count } => Output::Dropped(count),
+            RecordKind::Stage { index, record } => {
+                Output::Stage(index, Box::new(record.into_output()))
+            }
+            RecordKind::Progress { progress } => Output::Progress(progress),
+        }
+    }
+}
+
+impl Record {
+    /// Record `event` of the process `pid`
+    pub fn new(event: &Event, pid: Option<u32>) -> Self {
+        let read_at = SystemTime::now() - event.at.elapsed();
+        Self {
+            seq: event.seq,
+            elapsed_us: event.elapsed.as_micros() as u64,
+            timestamp: timestamp(read_at),
+            pid,
+            kind: RecordKind::new(&event.output),
+        }
+    }
+
+    /// Turn the record back into an [`Event`] of a process spawned at `started`
+    pub fn into_event(self, started: Instant) -> Event {
+        let elapsed = Duration::from_micros(self.elapsed_us);
+        Event {
+            seq: self.seq,
+            at: started + elapsed,
+            elapsed,
+            output: self.kind.into_output(),
+        }
+    }
+}
+
+/// Parse the `Debug` name of an [`io::ErrorKind`] back, falling back to `Other`
+fn error_kind(name: &str) -> io::ErrorKind {
+    use io::ErrorKind::*;
+    [
+        NotFound,
+        PermissionDenied,
+        ConnectionRefused,
+        ConnectionReset,
+        BrokenPipe,
+        AlreadyExists,
+        WouldBlock,
+        InvalidInput,
+        InvalidData,
+        TimedOut,
+        WriteZero,
+        Interrupted,
+        Unsupported,
+        UnexpectedEof,
+        OutOfMemory,
+    ]
+    .into_iter()
+    .find(|kind| format!("{kind:?}") == name)
+    .unwrap_or(Other)
+}
+
+/// Writes [`Event`]s as JSON lines
+pub struct JsonLinesEncoder<W> {
+    writer: W,
+    pid: Option<u32>,
+}
+
+impl<W: Write> JsonLinesEncoder<W> {
+    /// Encode events of the process `pid`, e.g. [`super::Process::id`]
+    pub fn new(writer: W, pid: Option<u32>) -> Self {
+        Self { writer, pid }
+    }
+
+    /// Write `event` as one line and flush it
+    pub fn encode(&mut self, event: &Event) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, &Record::new(event, self.pid))?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Reads [`Event`]s back from JSON lines written by [`JsonLinesEncoder`], skipping blank lines
+pub struct JsonLinesDecoder<R> {
+    lines: io::Lines<R>,
+    started: Instant,
+}
+
+impl<R: BufRead> JsonLinesDecoder<R> {
+    /// Decode events, placing them relative to a process spawned now
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for JsonLinesDecoder<R> {
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = loop {
+            match self.lines.next()? {
+                Ok(line) if line.trim().is_empty() => continue,
+                line => break line,
+            }
+        };
+        Some(line.and_then(|line| {
+            let record: Record = serde_json::from_str(&line)?;
+            Ok(record.into_event(self.started))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::ExitReason;
+
+    fn event(seq: u64, output: Output) -> Event {
+        Event {
+            seq,
+            at: Instant::now(),
+            elapsed: Duration::from_micros(seq * 100),
+            output,
+        }
+    }
+
+    #[test]
+    fn round_trips_every_kind() {
+        let exit = Exit {
+            code: None,
+            signal: Some(libc::SIGKILL),
+            core_dumped: false,
+            reason: ExitReason::Deadline(Duration::from_secs(1)),
+            usage: None,
+        };
+        let outputs = vec![
+            Output::Out("out".into()),
+            Output::Err("err".into()),
+            Output::OutBytes(vec![0, 255]),
+            Output::Dropped(3),
+            Output::Lifecycle(Lifecycle::Started {
+                pid: 7,
+                restarts: 1,
+            }),
+            Output::Stage(1, Box::new(Output::Err("stage".into()))),
+            Output::Progress(Progress {
+                current: Some(1),
+                total: Some(4),
+                percent: Some(25.0),
+            }),
+            Output::Exit(Ok(exit)),
+            Output::Exit(Err(io::Error::new(io::ErrorKind::NotFound, "gone"))),
+        ];
+        let mut encoder = JsonLinesEncoder::new(Vec::new(), Some(42));
+        for (seq, output) in outputs.into_iter().enumerate() {
+            encoder.encode(&event(seq as u64, output)).unwrap();
+        }
+        let encoded = encoder.into_inner();
+
+        let events: Vec<Event> = JsonLinesDecoder::new(&encoded[..])
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        assert_eq!(events.len(), 9);
+        assert_eq!(events[2].elapsed, Duration::from_micros(200));
+        assert!(matches!(&events[0].output, Output::Out(l) if l == "out"));
+        assert!(matches!(&events[2].output, Output::OutBytes(b) if b == &[0, 255]));
+        assert!(matches!(events[3].output, Output::Dropped(3)));
+        assert!(matches!(
+            &events[5].output,
+            Output::Stage(1, o) if matches!(&**o, Output::Err(l) if l == "stage")
+        ));
+        assert!(matches!(
+            events[6].output,
+            Output::Progress(Progress {
+                current: Some(1),
+                ..
+            })
+        ));
+        assert!(matches!(&events[7].output, Output::Exit(Ok(e)) if *e == exit));
+        assert!(matches!(
+            &events[8].output,
+            Output::Exit(Err(e)) if e.kind() == io::ErrorKind::NotFound && e.to_string() == "gone"
+        ));
+    }
+
+    #[test]
+    fn encodes_stable_fields() {
+        let mut encoder = JsonLinesEncoder::new(Vec::new(), Some(42));
+        encoder
+            .encode(&event(5, Output::Err("boom".into())))
+            .unwrap();
+
+        let line = String::from_utf8(encoder.into_inner()).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+
+        assert_eq!(json["seq"], 5);
+        assert_eq!(json["elapsed_us"], 500);
+        assert_eq!(json["pid"], 42);
+        assert_eq!(json["kind"], "line");
+        assert_eq!(json["stream"], "stderr");
+        assert_eq!(json["text"], "boom");
+        assert!(json["timestamp"].as_str().unwrap().ends_with('Z'));
+    }
+}
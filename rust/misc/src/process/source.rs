@@ -0,0 +1,245 @@
+//! Abstraction over where [`Output`] comes from, with a scripted fake for tests
+use super::event::OutputSender;
+use super::{
+    Event, Exit, ExitReason, ExitState, Output, OutputReceiver, OutputStream, Pipeline, Process,
+};
+use crossbeam_channel::unbounded;
+use std::io;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Something producing a stream of [`Output`] that ends with [`Output::Exit`].
+///
+/// Write consumers against this trait to test them with [`FakeProcess`] instead of spawning
+/// real binaries.
+pub trait OutputSource {
+    /// Get iteratorable stream of outputs
+    fn stream(&self) -> OutputStream<'_>;
+
+    /// Block until the source exits
+    fn wait(&self) -> io::Result<Exit>;
+}
+
+impl OutputSource for Process {
+    fn stream(&self) -> OutputStream<'_> {
+        Process::stream(self)
+    }
+
+    fn wait(&self) -> io::Result<Exit> {
+        Process::wait(self)
+    }
+}
+
+impl OutputSource for Pipeline {
+    fn stream(&self) -> OutputStream<'_> {
+        Pipeline::stream(self)
+    }
+
+    fn wait(&self) -> io::Result<Exit> {
+        Pipeline::wait(self)
+    }
+}
+
+/// Output and timing replayed by a [`FakeProcess`]
+#[derive(Debug, Default)]
+pub struct FakeScript {
+    steps: Vec<Step>,
+    exit: Option<io::Result<Exit>>,
+}
+
+#[derive(Debug)]
+enum Step {
+    Output(Output),
+    Delay(Duration),
+}
+
+impl FakeScript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replay recorded events with the gaps between them, e.g. from
+    /// [`super::JsonLinesDecoder`]. A recorded [`Output::Exit`] becomes the exit status.
+    pub fn from_events(events: impl IntoIterator<Item = Event>) -> Self {
+        let mut script = Self::new();
+        let mut last = Duration::ZERO;
+        for event in events {
+            script = script.delay(event.elapsed.saturating_sub(last));
+            last = event.elapsed;
+            script = match event.output {
+                Output::Exit(exit) => Self {
+                    exit: Some(exit),
+                    ..script
+                },
+                output => script.output(output),
+            };
+        }
+        script
+    }
+
+    /// Queue a stdout line
+    pub fn out(self, line: impl Into<String>) -> Self {
+        self.output(Output::Out(line.into()))
+    }
+
+    /// Queue a stderr line
+    pub fn err(self, line: impl Into<String>) -> Self {
+        self.output(Output::Err(line.into()))
+    }
+
+    /// Queue any output, [`Output::Exit`] is reported through [`FakeScript::exit`] instead
+    pub fn output(mut self, output: Output) -> Self {
+        self.steps.push(Step::Output(output));
+        self
+    }
+
+    /// Pause before the next step
+    pub fn delay(mut self, delay: Duration) -> Self {
+        if !delay.is_zero() {
+            self.steps.push(Step::Delay(delay));
+        }
+        self
+    }
+
+    /// Exit with `exit` once every step was replayed, code 0 by default
+    pub fn exit(mut self, exit: io::Result<Exit>) -> Self {
+        self.exit = Some(exit);
+        self
+    }
+
+    /// Exit with `code` once every step was replayed
+    pub fn exit_code(self, code: i32) -> Self {
+        self.exit(Ok(exited(code)))
+    }
+}
+
+fn exited(code: i32) -> Exit {
+    Exit {
+        code: Some(code),
+        signal: None,
+        core_dumped: false,
+        reason: ExitReason::Exited,
+        usage: None,
+    }
+}
+
+/// Stand-in for [`Process`] replaying a [`FakeScript`] on a background thread
+pub struct FakeProcess {
+    rx: OutputReceiver,
+    state: Arc<ExitState>,
+}
+
+impl FakeProcess {
+    /// Start replaying `script`
+    pub fn spawn(script: FakeScript) -> Self {
+        let (tx, rx) = unbounded();
+        let tx = OutputSender::new(tx);
+        let state = Arc::new(ExitState::default());
+        let exit = script.exit.unwrap_or(Ok(exited(0)));
+        {
+            let state = state.clone();
+            thread::spawn(move || {
+                for step in script.steps {
+                    match step {
+                        Step::Output(output) => {
+                            if tx.send(output).is_err() {
+                                break;
+                            }
+                        }
+                        Step::Delay(delay) => thread::sleep(delay),
+                    }
+                }
+                state.set(&exit);
+                tx.send(Output::Exit(exit)).ok();
+            });
+        }
+
+        FakeProcess { rx, state }
+    }
+}
+
+impl OutputSource for FakeProcess {
+    fn stream(&self) -> OutputStream<'_> {
+        OutputStream {
+            rx: &self.rx,
+            reorder: None,
+            // A single thread sends every event
+            sequential: true,
+            cursor: None,
+            exit: false,
+        }
+    }
+
+    fn wait(&self) -> io::Result<Exit> {
+        self.state.wait()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::{JsonLinesDecoder, JsonLinesEncoder};
+    use std::process::Command;
+    use std::time::Instant;
+
+    /// Consumer under test, unaware of where output comes from
+    fn errors(source: &impl OutputSource) -> (Vec<String>, Option<i32>) {
+        let errors = source
+            .stream()
+            .filter_map(|o| match o {
+                Output::Err(line) => Some(line),
+                _ => None,
+            })
+            .collect();
+        (errors, source.wait().unwrap().code)
+    }
+
+    #[test]
+    fn consumer_runs_against_real_and_fake() {
+        let process =
+            Process::new(Command::new("sh").args(["-c", "echo ok; echo bad >&2; exit 2"])).unwrap();
+        let fake = FakeProcess::spawn(FakeScript::new().out("ok").err("bad").exit_code(2));
+
+        let real = errors(&process);
+        assert_eq!(real, (vec!["bad".to_string()], Some(2)));
+        assert_eq!(errors(&fake), real);
+    }
+
+    #[test]
+    fn replays_with_delays() {
+        let script = FakeScript::new()
+            .out("first")
+            .delay(Duration::from_millis(100))
+            .out("second");
+        let fake = FakeProcess::spawn(script);
+        let mut stream = fake.stream();
+
+        assert!(matches!(stream.next(), Some(Output::Out(l)) if l == "first"));
+        let started = Instant::now();
+        assert!(matches!(stream.next(), Some(Output::Out(l)) if l == "second"));
+        assert!(started.elapsed() >= Duration::from_millis(90));
+        assert!(matches!(
+            stream.next(),
+            Some(Output::Exit(Ok(Exit { code: Some(0), .. })))
+        ));
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn replays_recorded_process() {
+        let process = Process::new(Command::new("sh").args(["-c", "echo rec; exit 4"])).unwrap();
+        let mut encoder = JsonLinesEncoder::new(Vec::new(), Some(process.id()));
+        for event in process.stream().events() {
+            encoder.encode(&event).unwrap();
+        }
+        let recorded = encoder.into_inner();
+
+        let events = JsonLinesDecoder::new(&recorded[..]).map(Result::unwrap);
+        let fake = FakeProcess::spawn(FakeScript::from_events(events));
+
+        let outputs: Vec<Output> = fake.stream().collect();
+        assert!(matches!(&outputs[0], Output::Out(l) if l == "rec"));
+        assert_eq!(fake.wait().unwrap().code, Some(4));
+    }
+}
@@ -0,0 +1,368 @@
+//! Restart supervision for long-running [`Process`] children
+use super::event::OutputSender;
+use super::{Exit, Output, OutputReceiver, OutputStream, Process, Shared};
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// When a [`Supervisor`] starts its child again
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RestartPolicy {
+    /// Run the child once
+    Never,
+    /// Restart unless the child exited with code 0
+    #[default]
+    OnFailure,
+    /// Restart whenever the child exits
+    Always,
+}
+
+/// Exponential delay between restarts
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    /// Delay before the first restart
+    pub initial: Duration,
+    /// Upper bound for the delay
+    pub max: Duration,
+    /// Factor applied to the delay after each consecutive restart
+    pub multiplier: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(30),
+            multiplier: 2,
+        }
+    }
+}
+
+impl Backoff {
+    /// Delay before the restart following `consecutive` quick restarts
+    fn delay(&self, consecutive: u32) -> Duration {
+        let factor = self.multiplier.saturating_pow(consecutive);
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+}
+
+/// Options controlling how a [`Supervisor`] restarts its child
+#[derive(Debug, Clone)]
+pub struct SupervisorOptions {
+    pub policy: RestartPolicy,
+    /// Delay between restarts. The delay resets once a child stayed up for longer than
+    /// [`Backoff::max`].
+    pub backoff: Backoff,
+    /// Give up once this many restarts happened within `restart_window`
+    pub max_restarts: Option<u32>,
+    pub restart_window: Duration,
+}
+
+impl Default for SupervisorOptions {
+    fn default() -> Self {
+        Self {
+            policy: RestartPolicy::default(),
+            backoff: Backoff::default(),
+            max_restarts: None,
+            restart_window: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Supervision events interleaved with the child's output
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Lifecycle {
+    /// A child was spawned, `restarts` counts the restarts before it
+    Started { pid: u32, restarts: u32 },
+    /// The current child exited
+    Exited(Exit),
+    /// The child will be spawned again after `delay`
+    Restarting { restarts: u32, delay: Duration },
+    /// `max_restarts` was reached within the restart window, supervision ends
+    GaveUp { restarts: u32 },
+}
+
+impl fmt::Display for Lifecycle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Lifecycle::Started { pid, restarts } => {
+                write!(f, "[Supervisor] started pid {pid} (restarts: {restarts})")
+            }
+            Lifecycle::Exited(exit) => write!(f, "[Supervisor] child {exit}"),
+            Lifecycle::Restarting { delay, .. } => {
+                write!(f, "[Supervisor] restarting in {delay:?}")
+            }
+            Lifecycle::GaveUp { restarts } => {
+                write!(f, "[Supervisor] giving up after {restarts} restarts")
+            }
+        }
+    }
+}
+
+/// Keeps a [`Process`] running according to a [`RestartPolicy`].
+///
+/// Output of every child run arrives on one stream together with [`Output::Lifecycle`]
+/// events. The stream ends with a single [`Output::Exit`] carrying the last child's exit once
+/// supervision stops.
+pub struct Supervisor {
+    rx: OutputReceiver,
+    current: Arc<Mutex<Option<Arc<Shared>>>>,
+    stop: Sender<()>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Supervisor {
+    /// Start supervising processes created by `spawn`, which is called again for each restart
+    pub fn spawn<F>(spawn: F, options: SupervisorOptions) -> Supervisor
+    where
+        F: FnMut() -> io::Result<Process> + Send + 'static,
+    {
+        let (tx, rx) = unbounded();
+        let (stop, stop_rx) = unbounded();
+        let current = Arc::new(Mutex::new(None));
+        let handle = {
+            let tx = OutputSender::new(tx);
+            let current = current.clone();
+            thread::spawn(move || supervise(spawn, options, tx, stop_rx, current))
+        };
+
+        Supervisor {
+            rx,
+            current,
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    /// Get iteratorable stream of outputs across all child runs
+    pub fn stream(&self) -> OutputStream<'_> {
+        OutputStream {
+            rx: &self.rx,
+            reorder: None,
+            // A single thread sends every event
+            sequential: true,
+            cursor: None,
+            exit: false,
+        }
+    }
+
+    /// Stop restarting and kill the current child. The stream still ends with [`Output::Exit`].
+    pub fn stop(&self) -> io::Result<()> {
+        self.stop.send(()).ok();
+        match self
+            .current
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+        {
+            Some(shared) => shared.signal(libc::SIGKILL),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        self.stop().ok();
+        if let Some(handle) = self.handle.take() {
+            handle.join().ok();
+        }
+    }
+}
+
+fn supervise<F>(
+    mut spawn: F,
+    options: SupervisorOptions,
+    tx: OutputSender,
+    stop: Receiver<()>,
+    current: Arc<Mutex<Option<Arc<Shared>>>>,
+) where
+    F: FnMut() -> io::Result<Process>,
+{
+    let mut restarts = 0;
+    let mut consecutive = 0;
+    let mut recent = VecDeque::new();
+    loop {
+        let started = Instant::now();
+        let exit = match spawn() {
+            Ok(process) => {
+                *current.lock().unwrap_or_else(|e| e.into_inner()) = Some(process.shared.clone());
+                if !stop.is_empty() {
+                    process.shared.signal(libc::SIGKILL).ok();
+                }
+                let pid = process.id();
+                tx.send(Output::Lifecycle(Lifecycle::Started { pid, restarts }))
+                    .ok();
+                let mut exit = Err(io::Error::other("process output ended without exit"));
+                for output in process.stream() {
+                    match output {
+                        Output::Exit(result) => exit = result,
+                        output => {
+                            tx.send(output).ok();
+                        }
+                    }
+                }
+                current.lock().unwrap_or_else(|e| e.into_inner()).take();
+                exit
+            }
+            Err(e) => Err(e),
+        };
+        match &exit {
+            Ok(exit) => tx.send(Output::Lifecycle(Lifecycle::Exited(*exit))).ok(),
+            Err(e) => tx
+                .send(Output::Err(format!("supervised process: {e}")))
+                .ok(),
+        };
+
+        let failed = !matches!(&exit, Ok(exit) if exit.success());
+        let restart = match options.policy {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => failed,
+            RestartPolicy::Always => true,
+        };
+        if !restart || !stop.is_empty() {
+            tx.send(Output::Exit(exit)).ok();
+            return;
+        }
+
+        let now = Instant::now();
+        while recent
+            .front()
+            .is_some_and(|&at| now.duration_since(at) > options.restart_window)
+        {
+            recent.pop_front();
+        }
+        if options
+            .max_restarts
+            .is_some_and(|max| recent.len() >= max as usize)
+        {
+            tx.send(Output::Lifecycle(Lifecycle::GaveUp { restarts }))
+                .ok();
+            tx.send(Output::Exit(exit)).ok();
+            return;
+        }
+
+        if started.elapsed() > options.backoff.max {
+            consecutive = 0;
+        }
+        let delay = options.backoff.delay(consecutive);
+        consecutive += 1;
+        restarts += 1;
+        recent.push_back(now);
+        tx.send(Output::Lifecycle(Lifecycle::Restarting { restarts, delay }))
+            .ok();
+        if !matches!(stop.recv_timeout(delay), Err(RecvTimeoutError::Timeout)) {
+            tx.send(Output::Exit(exit)).ok();
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn fast(policy: RestartPolicy, max_restarts: Option<u32>) -> SupervisorOptions {
+        SupervisorOptions {
+            policy,
+            backoff: Backoff {
+                initial: Duration::from_millis(10),
+                max: Duration::from_millis(40),
+                multiplier: 2,
+            },
+            max_restarts,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn backoff_grows_and_caps() {
+        let backoff = Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(1),
+            multiplier: 2,
+        };
+
+        assert_eq!(backoff.delay(0), Duration::from_millis(100));
+        assert_eq!(backoff.delay(2), Duration::from_millis(400));
+        assert_eq!(backoff.delay(10), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn restarts_failing_child_until_limit() {
+        let supervisor = Supervisor::spawn(
+            || Process::new(Command::new("sh").args(["-c", "echo run; exit 1"])),
+            fast(RestartPolicy::OnFailure, Some(2)),
+        );
+
+        let outputs: Vec<Output> = supervisor.stream().collect();
+
+        let started = outputs
+            .iter()
+            .filter(|o| matches!(o, Output::Lifecycle(Lifecycle::Started { .. })))
+            .count();
+        let runs = outputs
+            .iter()
+            .filter(|o| matches!(o, Output::Out(l) if l == "run"))
+            .count();
+        assert_eq!((started, runs), (3, 3));
+        assert!(matches!(
+            outputs[outputs.len() - 2],
+            Output::Lifecycle(Lifecycle::GaveUp { restarts: 2 })
+        ));
+        assert!(matches!(
+            outputs.last(),
+            Some(Output::Exit(Ok(Exit { code: Some(1), .. })))
+        ));
+    }
+
+    #[test]
+    fn on_failure_stops_after_success() {
+        let supervisor = Supervisor::spawn(
+            || Process::new(&mut Command::new("true")),
+            fast(RestartPolicy::OnFailure, None),
+        );
+
+        let outputs: Vec<Output> = supervisor.stream().collect();
+
+        assert!(matches!(
+            outputs[0],
+            Output::Lifecycle(Lifecycle::Started { restarts: 0, .. })
+        ));
+        assert!(matches!(
+            outputs[1],
+            Output::Lifecycle(Lifecycle::Exited(_))
+        ));
+        assert!(matches!(
+            outputs[2],
+            Output::Exit(Ok(Exit { code: Some(0), .. }))
+        ));
+    }
+
+    #[test]
+    fn stop_ends_supervision() {
+        let supervisor = Supervisor::spawn(
+            || Process::new(Command::new("sleep").arg("30")),
+            fast(RestartPolicy::Always, None),
+        );
+        let mut stream = supervisor.stream();
+        assert!(matches!(
+            stream.next(),
+            Some(Output::Lifecycle(Lifecycle::Started { .. }))
+        ));
+
+        supervisor.stop().unwrap();
+
+        assert!(matches!(
+            stream.last(),
+            Some(Output::Exit(Ok(Exit { code: None, .. })))
+        ));
+    }
+}
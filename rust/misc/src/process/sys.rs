@@ -0,0 +1,173 @@
+//! Thin wrappers around the libc calls used by [`super::Process`]
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+/// Block until the process `pid` has exited without reaping it.
+///
+/// The zombie is left in place so that the owner of the [`std::process::Child`] can reap it
+/// while holding its lock, which guarantees the pid is never signalled after reuse.
+pub fn wait_exited(pid: u32) -> io::Result<()> {
+    loop {
+        let mut info = MaybeUninit::<libc::siginfo_t>::zeroed();
+        let res = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                pid as libc::id_t,
+                info.as_mut_ptr(),
+                libc::WEXITED | libc::WNOWAIT,
+            )
+        };
+        if res == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+/// Whether the process `pid` has exited, without waiting for it or reaping it
+pub fn has_exited(pid: u32) -> io::Result<bool> {
+    let mut info = MaybeUninit::<libc::siginfo_t>::zeroed();
+    let res = unsafe {
+        libc::waitid(
+            libc::P_PID,
+            pid as libc::id_t,
+            info.as_mut_ptr(),
+            libc::WEXITED | libc::WNOWAIT | libc::WNOHANG,
+        )
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // With WNOHANG a still running child leaves `si_pid` zeroed
+    Ok(unsafe { info.assume_init().si_pid() } != 0)
+}
+
+/// Send `signal` to the process `pid`
+pub fn signal(pid: u32, signal: libc::c_int) -> io::Result<()> {
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Send `signal` to every process in the group led by `pgid`
+pub fn signal_group(pgid: u32, signal: libc::c_int) -> io::Result<()> {
+    if unsafe { libc::killpg(pgid as libc::pid_t, signal) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Make the calling process lead a new session. Only async-signal-safe calls, for `pre_exec`.
+///
+/// Succeeds when the process already leads its session, as it does when the hook of an
+/// earlier spawn of the same `Command` ran first.
+pub fn setsid() -> io::Result<()> {
+    if unsafe { libc::getsid(0) == libc::getpid() } {
+        return Ok(());
+    }
+    if unsafe { libc::setsid() } == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Open a pseudo-terminal pair sized `rows` x `cols`, returning `(master, slave)`.
+pub fn openpty(rows: u16, cols: u16) -> io::Result<(OwnedFd, OwnedFd)> {
+    let (mut master, mut slave) = (0, 0);
+    let size = winsize(rows, cols);
+    let res = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            &size,
+        )
+    };
+    if res == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let (master, slave) = unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+    for fd in [&master, &slave] {
+        if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok((master, slave))
+}
+
+/// Resize the terminal behind `fd`, delivering SIGWINCH to its foreground group.
+pub fn set_winsize(fd: &impl AsRawFd, rows: u16, cols: u16) -> io::Result<()> {
+    let size = winsize(rows, cols);
+    if unsafe { libc::ioctl(fd.as_raw_fd(), libc::TIOCSWINSZ, &size) } == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Start a new session and make stdin its controlling terminal. For `pre_exec`.
+///
+/// Like [`setsid`], succeeds when an earlier hook of the same `Command` already did so:
+/// taking a terminal that already controls the session is a no-op.
+pub fn set_controlling_terminal() -> io::Result<()> {
+    setsid()?;
+    if unsafe { libc::ioctl(0, libc::TIOCSCTTY as _, 0) } == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+fn winsize(rows: u16, cols: u16) -> libc::winsize {
+    libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub type Resource = libc::__rlimit_resource_t;
+#[cfg(not(target_os = "linux"))]
+pub type Resource = libc::c_int;
+
+/// Set both the soft and hard limit of `resource`. Async-signal-safe, for `pre_exec`.
+pub fn setrlimit(resource: Resource, value: u64) -> io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: value as libc::rlim_t,
+        rlim_max: value as libc::rlim_t,
+    };
+    if unsafe { libc::setrlimit(resource, &limit) } == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Reap the exited process `pid`, returning its status and resource usage.
+pub fn wait4(pid: u32) -> io::Result<(ExitStatus, libc::rusage)> {
+    loop {
+        let mut status = 0;
+        let mut usage = MaybeUninit::<libc::rusage>::zeroed();
+        let res = unsafe { libc::wait4(pid as libc::pid_t, &mut status, 0, usage.as_mut_ptr()) };
+        if res != -1 {
+            return Ok((ExitStatus::from_raw(status), unsafe { usage.assume_init() }));
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
@@ -0,0 +1,309 @@
+//! Mirror [`super::Process`] output to log files on disk
+use super::{Output, ReadMode};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where and how stdout and stderr are written to disk.
+///
+/// Every write goes straight to the file without buffering in the process, so the log is
+/// complete up to the last line read even if the program driving the child crashes.
+#[derive(Debug, Clone)]
+pub struct LogOptions {
+    stdout: PathBuf,
+    /// Separate stderr file, stderr lines go to the stdout file with an `[Error]` tag otherwise
+    stderr: Option<PathBuf>,
+    timestamps: bool,
+    rotation: Option<Rotation>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Rotation {
+    max_bytes: u64,
+    keep: usize,
+    compress: bool,
+}
+
+impl LogOptions {
+    /// Write stdout and stderr to one file, tagging stderr lines with `[Error]`
+    pub fn combined(path: impl Into<PathBuf>) -> Self {
+        Self {
+            stdout: path.into(),
+            stderr: None,
+            timestamps: false,
+            rotation: None,
+        }
+    }
+
+    /// Write stdout and stderr to separate files
+    pub fn split(stdout: impl Into<PathBuf>, stderr: impl Into<PathBuf>) -> Self {
+        Self {
+            stderr: Some(stderr.into()),
+            ..Self::combined(stdout)
+        }
+    }
+
+    /// Prefix each line with the UTC time it was read, e.g. `2022-04-02T10:20:30.123Z`
+    pub fn timestamps(mut self) -> Self {
+        self.timestamps = true;
+        self
+    }
+
+    /// Start a new file once the current one would grow beyond `max_bytes`, keeping up to
+    /// `keep` rotated files as `<path>.1` (newest) to `<path>.<keep>`
+    pub fn rotate(mut self, max_bytes: u64, keep: usize) -> Self {
+        self.rotation = Some(Rotation {
+            max_bytes,
+            keep,
+            compress: false,
+        });
+        self
+    }
+
+    /// Gzip rotated files as `<path>.<n>.gz`, only has an effect together with
+    /// [`LogOptions::rotate`]
+    pub fn compress(mut self) -> Self {
+        if let Some(rotation) = &mut self.rotation {
+            rotation.compress = true;
+        }
+        self
+    }
+}
+
+/// Open log files of one process, written by every reader thread
+pub(super) struct Tee {
+    files: Mutex<Files>,
+    /// Appended after each item, lines lost their line ending when read
+    separator: &'static [u8],
+    timestamps: bool,
+}
+
+struct Files {
+    stdout: LogFile,
+    stderr: Option<LogFile>,
+    /// Set after a failed write, logging stops from then on
+    failed: bool,
+}
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    rotation: Option<Rotation>,
+    /// Whether the next write starts a line and gets prefixed
+    line_start: bool,
+}
+
+impl Tee {
+    /// Create or append to the configured files
+    pub fn open(options: LogOptions, mode: ReadMode) -> io::Result<Self> {
+        let rotation = options.rotation;
+        let stderr = options.stderr.map(|path| LogFile::open(path, rotation));
+        Ok(Self {
+            files: Mutex::new(Files {
+                stdout: LogFile::open(options.stdout, rotation)?,
+                stderr: stderr.transpose()?,
+                failed: false,
+            }),
+            separator: match mode {
+                ReadMode::Lines | ReadMode::Bytes => b"\n",
+                ReadMode::Chunks(_) => b"",
+            },
+            timestamps: options.timestamps,
+        })
+    }
+
+    /// Write `output` if it is stdout or stderr. Only the first failure is returned, after
+    /// which nothing is written anymore.
+    pub fn write(&self, output: &Output) -> io::Result<()> {
+        let (stdout, data) = match output {
+            Output::Out(line) => (true, line.as_bytes()),
+            Output::Err(line) => (false, line.as_bytes()),
+            Output::OutBytes(bytes) => (true, &bytes[..]),
+            Output::ErrBytes(bytes) => (false, &bytes[..]),
+            _ => return Ok(()),
+        };
+        let mut files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+        if files.failed {
+            return Ok(());
+        }
+        let files = &mut *files;
+        let (file, tag) = match (stdout, files.stderr.as_mut()) {
+            (true, _) => (&mut files.stdout, None),
+            (false, Some(stderr)) => (stderr, None),
+            (false, None) => (&mut files.stdout, Some(&b"[Error] "[..])),
+        };
+
+        let mut buf = Vec::with_capacity(data.len() + 32);
+        let data = [data, self.separator].concat();
+        for piece in data.split_inclusive(|&b| b == b'\n') {
+            if file.line_start {
+                if self.timestamps {
+                    buf.extend_from_slice(timestamp(SystemTime::now()).as_bytes());
+                    buf.push(b' ');
+                }
+                buf.extend_from_slice(tag.unwrap_or_default());
+            }
+            buf.extend_from_slice(piece);
+            file.line_start = piece.ends_with(b"\n");
+        }
+        let result = file
+            .write(&buf)
+            .map_err(|e| io::Error::new(e.kind(), format!("log {}: {e}", file.path.display())));
+        files.failed = result.is_err();
+        result
+    }
+}
+
+impl LogFile {
+    fn open(path: PathBuf, rotation: Option<Rotation>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self {
+            size: file.metadata()?.len(),
+            path,
+            file,
+            rotation,
+            line_start: true,
+        })
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        if let Some(rotation) = self.rotation {
+            if self.size > 0 && self.size + buf.len() as u64 > rotation.max_bytes {
+                self.rotate(rotation)?;
+            }
+        }
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        Ok(())
+    }
+
+    /// Shift `<path>.<n>` to `<path>.<n + 1>`, move the current file to `<path>.1` and start
+    /// over with an empty one
+    fn rotate(&mut self, rotation: Rotation) -> io::Result<()> {
+        let rotated = |n: usize| {
+            let mut path = OsString::from(&self.path);
+            path.push(format!(".{n}"));
+            if rotation.compress {
+                path.push(".gz");
+            }
+            PathBuf::from(path)
+        };
+        if rotation.keep > 0 {
+            for n in (1..rotation.keep).rev() {
+                match fs::rename(rotated(n), rotated(n + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            if rotation.compress {
+                gzip(&self.path, &rotated(1))?;
+            } else {
+                fs::rename(&self.path, rotated(1))?;
+            }
+        }
+        self.file = File::create(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn gzip(from: &Path, to: &Path) -> io::Result<()> {
+    let mut encoder = GzEncoder::new(File::create(to)?, Compression::default());
+    io::copy(&mut File::open(from)?, &mut encoder)?;
+    encoder.finish()?;
+    Ok(())
+}
+
+/// RFC 3339 UTC timestamp with milliseconds
+pub(super) fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // Days since 1970-01-01 to a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        secs_of_day / 3_600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+    use std::time::Duration;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("misc-tee-{name}-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn formats_utc_timestamp() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_648_894_830_123);
+        assert_eq!(timestamp(time), "2022-04-02T10:20:30.123Z");
+        assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn combined_file_tags_stderr() {
+        let dir = temp_dir("combined");
+        let path = dir.join("out.log");
+        let tee = Tee::open(LogOptions::combined(&path), ReadMode::Lines).unwrap();
+
+        tee.write(&Output::Out("one".into())).unwrap();
+        tee.write(&Output::Err("two".into())).unwrap();
+        tee.write(&Output::Dropped(1)).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\n[Error] two\n");
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn rotates_and_compresses() {
+        let dir = temp_dir("rotate");
+        let (out, err) = (dir.join("out.log"), dir.join("err.log"));
+        let options = LogOptions::split(&out, &err).rotate(8, 2).compress();
+        let tee = Tee::open(options, ReadMode::Lines).unwrap();
+
+        for line in ["aaaa", "bbbb", "cccc", "dddd"] {
+            tee.write(&Output::Out(line.into())).unwrap();
+        }
+        tee.write(&Output::Err("e".into())).unwrap();
+
+        let gunzip = |n: usize| {
+            let mut text = String::new();
+            let file = File::open(dir.join(format!("out.log.{n}.gz"))).unwrap();
+            GzDecoder::new(file).read_to_string(&mut text).unwrap();
+            text
+        };
+        assert_eq!(fs::read_to_string(&out).unwrap(), "dddd\n");
+        assert_eq!(gunzip(1), "cccc\n");
+        assert_eq!(gunzip(2), "bbbb\n");
+        assert!(!dir.join("out.log.3.gz").exists());
+        assert_eq!(fs::read_to_string(&err).unwrap(), "e\n");
+        fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,80 @@
+//! Deadline and idle-output watchdog for [`super::Process`]
+use super::{sys, ExitReason, Shared};
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Time of the latest output line, shared by the reader threads and the watchdog
+pub(super) struct Activity {
+    started: Instant,
+    last: AtomicU64,
+}
+
+impl Activity {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            last: AtomicU64::new(0),
+        }
+    }
+
+    pub fn touch(&self) {
+        let elapsed = self.started.elapsed().as_nanos() as u64;
+        self.last.store(elapsed, Ordering::Relaxed);
+    }
+
+    fn last(&self) -> Instant {
+        self.started + Duration::from_nanos(self.last.load(Ordering::Relaxed))
+    }
+}
+
+/// Kill the child once `deadline` passed since spawn or `idle` passed since the last output.
+///
+/// The thread sleeps on the [`super::ExitState`] condvar, so it returns as soon as the child is reaped.
+pub(super) fn spawn_watchdog(
+    shared: Arc<Shared>,
+    activity: Arc<Activity>,
+    deadline: Option<Duration>,
+    idle: Option<Duration>,
+) {
+    thread::spawn(move || {
+        let deadline_at = deadline.map(|d| (activity.started + d, ExitReason::Deadline(d)));
+        loop {
+            let idle_at = idle.map(|d| (activity.last() + d, ExitReason::Idle(d)));
+            let (wake, reason) = match (deadline_at, idle_at) {
+                (Some(a), Some(b)) => {
+                    if a.0 <= b.0 {
+                        a
+                    } else {
+                        b
+                    }
+                }
+                (Some(a), None) | (None, Some(a)) => a,
+                (None, None) => return,
+            };
+
+            let now = Instant::now();
+            if now >= wake {
+                expire(&shared, reason).ok();
+                return;
+            }
+            if shared.state.wait_timeout(wake - now) {
+                return;
+            }
+        }
+    });
+}
+
+/// Kill the child for `reason` unless it already exited on its own
+fn expire(shared: &Shared, reason: ExitReason) -> io::Result<()> {
+    let child = shared.child.lock().unwrap_or_else(|e| e.into_inner());
+    // The status thread reaps and reads the reason under this lock, so a child that exited
+    // on its own right at the deadline is still an unreaped zombie here and keeps its reason.
+    if shared.state.is_reaped() || sys::has_exited(child.id())? {
+        return Ok(());
+    }
+    shared.state.set_reason(reason);
+    shared.signal_locked(&child, libc::SIGKILL)
+}